@@ -0,0 +1,54 @@
+use crate::*;
+use bitsy_script::Val;
+use firefly_rust as ff;
+
+/// Award badges for reaching the given ending.
+pub fn award_ending(state: &mut State, ending_id: &str) {
+    for i in 0..state.config.badges.len() {
+        let rule = &state.config.badges[i];
+        if let Trigger::Ending(id) = &rule.trigger
+            && id == ending_id
+        {
+            award(state, rule.badge);
+        }
+    }
+}
+
+/// Award badges for picking up the given item.
+pub fn award_item(state: &mut State, item_id: &str) {
+    for i in 0..state.config.badges.len() {
+        let rule = &state.config.badges[i];
+        if let Trigger::Item(id) = &rule.trigger
+            && id == item_id
+        {
+            award(state, rule.badge);
+        }
+    }
+}
+
+/// Award badges for variables that reached their thresholds.
+pub fn award_vars(state: &mut State) {
+    for i in 0..state.config.badges.len() {
+        let rule = &state.config.badges[i];
+        let Trigger::Var(name, threshold) = &rule.trigger else {
+            continue;
+        };
+        let reached = match state.script_state.vars.get(name) {
+            Val::I(v) => v >= threshold,
+            Val::F(v) => *v >= f32::from(*threshold),
+            _ => false,
+        };
+        if reached {
+            award(state, rule.badge);
+        }
+    }
+}
+
+/// Earn the badge unless it was already earned in this session.
+pub fn award(state: &mut State, badge: ff::Badge) {
+    if state.earned_badges.contains(&badge.0) {
+        return;
+    }
+    state.earned_badges.push(badge.0);
+    ff::add_progress(ff::Peer::COMBINED, badge, 1);
+}
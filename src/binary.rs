@@ -0,0 +1,506 @@
+use crate::*;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bitsy_file as bf;
+use bitsy_script as bs;
+
+/// The first bytes of a precompiled game file.
+pub const MAGIC: &[u8] = b"BITSYBIN";
+/// The version of the precompiled game format.
+///
+/// Must be bumped on every incompatible change in the format,
+/// including changes in how dialogs are tokenized.
+pub const VERSION: u8 = 2;
+/// The string index marking a missing optional value.
+pub const NONE: u16 = 0xFFFF;
+/// The colour marking a missing optional colour.
+pub const NO_COLOUR: u8 = 0xFF;
+
+/// Check if the game file is precompiled by `tools/bitsy-bin`.
+pub fn is_binary(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// A game precompiled by `tools/bitsy-bin`.
+pub struct Binary {
+    /// The game with all dialogs and endings left empty.
+    pub game: bf::Game,
+    /// The avatar overrides for rooms, see [`State::room_avatars`].
+    pub room_avatars: Vec<(String, String)>,
+    pub scripts: Scripts,
+    pub frames: PackedFrames,
+}
+
+/// Pre-tokenized dialogs and endings.
+///
+/// The indices match the indices of dialogs and endings in [`bf::Game`].
+/// Empty if the game is loaded from the text file.
+#[derive(Default)]
+pub struct Scripts {
+    pub dialogs: Vec<Vec<bs::Token>>,
+    pub endings: Vec<Vec<bs::Token>>,
+}
+
+/// Animation frames of tiles, sprites, and items in the Firefly image format.
+///
+/// The indices match the indices of tiles, sprites, and items in [`bf::Game`],
+/// whose own animation frames are left empty.
+/// Empty if the game is loaded from the text file, see [`State::tile_frames`].
+#[derive(Default)]
+pub struct PackedFrames {
+    pub tiles: Vec<Vec<PackedImage>>,
+    pub sprites: Vec<Vec<PackedImage>>,
+    pub items: Vec<Vec<PackedImage>>,
+}
+
+/// An image in the Firefly image format with the pixel value 1 for set bits.
+///
+/// Kept in the format it's drawn in, so it doesn't need to be converted
+/// and takes less memory than a [`bf::Image`] with one byte per pixel.
+pub struct PackedImage {
+    raw: Box<[u8]>,
+}
+
+impl PackedImage {
+    /// The header and the pixels, two pixels per byte.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.raw
+    }
+
+    /// The width (and the height) of the image in pixels.
+    pub fn width(&self) -> i32 {
+        i32::from(u16::from_le_bytes([self.raw[1], self.raw[2]]))
+    }
+
+    /// Check if the pixel with the given index is set.
+    pub fn is_set(&self, i: usize) -> bool {
+        let Some(byte) = self.raw.get(IMAGE_HEADER_SIZE + i / 2) else {
+            return false;
+        };
+        let pixel = if i.is_multiple_of(2) {
+            byte >> 4
+        } else {
+            byte & 0xF
+        };
+        pixel != 0
+    }
+}
+
+/// Load a game precompiled by `tools/bitsy-bin`.
+///
+/// The format is a sequence of little-endian values:
+///
+/// 1. The magic bytes and the format version.
+/// 2. The string table: all IDs and names used in the game.
+///    Everywhere else, strings are referred to by their index in the table.
+/// 3. The game title, palettes, tiles, sprites, items, rooms,
+///    dialogs, endings, and variables, in that order.
+///
+/// Images are stored in the Firefly image format with the pixel value 1 for set bits
+/// and stay in this format in memory, see [`PackedImage`].
+/// Dialogs are stored as script tokens.
+pub fn load_binary(data: &[u8]) -> Result<Binary, LoadError> {
+    let Some(data) = data.strip_prefix(MAGIC) else {
+        return Err(LoadError::InvalidBinary);
+    };
+    let mut reader = Reader {
+        data,
+        strings: Vec::new(),
+    };
+    if reader.u8() != Some(VERSION) {
+        return Err(LoadError::WrongVersion);
+    }
+    read_game(&mut reader).ok_or(LoadError::InvalidBinary)
+}
+
+/// Read everything after the format version.
+fn read_game(reader: &mut Reader<'_>) -> Option<Binary> {
+    let n_strings = reader.u16()?;
+    for _ in 0..n_strings {
+        let s = reader.str()?;
+        reader.strings.push(s);
+    }
+
+    let mut game = bf::Game::default();
+    let mut frames = PackedFrames::default();
+    game.name = reader.str()?;
+    for _ in 0..reader.u16()? {
+        game.palettes.push(reader.palette()?);
+    }
+    for _ in 0..reader.u16()? {
+        game.tiles.push(reader.tile()?);
+        frames.tiles.push(reader.frames()?);
+    }
+    for _ in 0..reader.u16()? {
+        game.sprites.push(reader.sprite()?);
+        frames.sprites.push(reader.frames()?);
+    }
+    for _ in 0..reader.u16()? {
+        game.items.push(reader.item()?);
+        frames.items.push(reader.frames()?);
+    }
+    let mut room_avatars = Vec::new();
+    for _ in 0..reader.u16()? {
+        let (room, avatar) = reader.room()?;
+        if let Some(avatar) = avatar {
+            room_avatars.push((room.id.clone(), avatar));
+        }
+        game.rooms.push(room);
+    }
+    let mut scripts = Scripts::default();
+    for _ in 0..reader.u16()? {
+        let id = reader.id()?;
+        let name = reader.opt_id()?;
+        let contents = String::new();
+        game.dialogues.push(bf::Dialogue { id, contents, name });
+        scripts.dialogs.push(reader.tokens()?);
+    }
+    for _ in 0..reader.u16()? {
+        let id = reader.id()?;
+        let dialogue = String::new();
+        game.endings.push(bf::Ending { id, dialogue });
+        scripts.endings.push(reader.tokens()?);
+    }
+    for _ in 0..reader.u16()? {
+        let id = reader.id()?;
+        let initial_value = reader.str()?;
+        game.variables.push(bf::Variable { id, initial_value });
+    }
+    Some(Binary {
+        game,
+        room_avatars,
+        scripts,
+        frames,
+    })
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    strings: Vec<String>,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, n: usize) -> Option<&[u8]> {
+        let (head, tail) = self.data.split_at_checked(n)?;
+        self.data = tail;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        let raw = self.bytes(2)?;
+        Some(u16::from_le_bytes([raw[0], raw[1]]))
+    }
+
+    fn str(&mut self) -> Option<String> {
+        let len = usize::from(self.u16()?);
+        let raw = self.bytes(len)?;
+        let s = core::str::from_utf8(raw).ok()?;
+        Some(s.to_string())
+    }
+
+    fn id(&mut self) -> Option<String> {
+        let idx = self.u16()?;
+        self.strings.get(usize::from(idx)).cloned()
+    }
+
+    fn opt_id(&mut self) -> Option<Option<String>> {
+        let idx = self.u16()?;
+        if idx == NONE {
+            return Some(None);
+        }
+        let s = self.strings.get(usize::from(idx))?;
+        Some(Some(s.clone()))
+    }
+
+    fn colour(&mut self) -> Option<Option<u64>> {
+        let colour = self.u8()?;
+        if colour == NO_COLOUR {
+            return Some(None);
+        }
+        Some(Some(u64::from(colour)))
+    }
+
+    fn pos(&mut self) -> Option<bf::Position> {
+        let x = self.u8()?;
+        let y = self.u8()?;
+        Some(bf::Position { x, y })
+    }
+
+    fn frames(&mut self) -> Option<Vec<PackedImage>> {
+        let n = self.u8()?;
+        let mut frames = Vec::new();
+        for _ in 0..n {
+            frames.push(self.image()?);
+        }
+        Some(frames)
+    }
+
+    /// Read an image in the Firefly format.
+    fn image(&mut self) -> Option<PackedImage> {
+        let header = self.bytes(IMAGE_HEADER_SIZE)?;
+        if header[0] != IMAGE_MAGIC {
+            return None;
+        }
+        let width = usize::from(u16::from_le_bytes([header[1], header[2]]));
+        if width != 8 && width != 16 {
+            return None;
+        }
+        let mut raw = header.to_vec();
+        let body = self.bytes(width * width / 2)?;
+        // Each pixel must be 0 or 1 to be recolored when drawn.
+        if body.iter().any(|byte| byte & 0xEE != 0) {
+            return None;
+        }
+        raw.extend_from_slice(body);
+        Some(PackedImage { raw: raw.into() })
+    }
+
+    fn palette(&mut self) -> Option<bf::Palette> {
+        let id = self.id()?;
+        let name = self.opt_id()?;
+        let n = self.u8()?;
+        let mut colours = Vec::new();
+        for _ in 0..n {
+            let rgb = self.bytes(3)?;
+            colours.push(bf::Colour {
+                red: rgb[0],
+                green: rgb[1],
+                blue: rgb[2],
+            });
+        }
+        Some(bf::Palette { id, name, colours })
+    }
+
+    fn tile(&mut self) -> Option<bf::Tile> {
+        let id = self.id()?;
+        let name = self.opt_id()?;
+        let wall = match self.u8()? {
+            0 => None,
+            1 => Some(false),
+            _ => Some(true),
+        };
+        let colour_id = self.colour()?;
+        Some(bf::Tile {
+            id,
+            name,
+            wall,
+            animation_frames: Vec::new(),
+            colour_id,
+        })
+    }
+
+    fn sprite(&mut self) -> Option<bf::Sprite> {
+        let id = self.id()?;
+        let name = self.opt_id()?;
+        let dialogue_id = self.opt_id()?;
+        let room_id = self.opt_id()?;
+        let position = match self.u8()? {
+            0 => None,
+            _ => Some(self.pos()?),
+        };
+        let colour_id = self.colour()?;
+        let mut items = Vec::new();
+        for _ in 0..self.u8()? {
+            items.push(self.id()?);
+        }
+        Some(bf::Sprite {
+            id,
+            name,
+            animation_frames: Vec::new(),
+            dialogue_id,
+            room_id,
+            position,
+            colour_id,
+            items,
+        })
+    }
+
+    fn item(&mut self) -> Option<bf::Item> {
+        let id = self.id()?;
+        let name = self.opt_id()?;
+        let dialogue_id = self.opt_id()?;
+        let colour_id = self.colour()?;
+        Some(bf::Item {
+            id,
+            animation_frames: Vec::new(),
+            name,
+            dialogue_id,
+            colour_id,
+        })
+    }
+
+    fn room(&mut self) -> Option<(bf::Room, Option<String>)> {
+        let id = self.id()?;
+        let palette_id = self.opt_id()?;
+        let name = self.opt_id()?;
+        let avatar = self.opt_id()?;
+        let mut tiles = Vec::new();
+        for _ in 0..self.u16()? {
+            tiles.push(self.id()?);
+        }
+        let mut items = Vec::new();
+        for _ in 0..self.u8()? {
+            let id = self.id()?;
+            let position = self.pos()?;
+            items.push(bf::Instance { position, id });
+        }
+        let mut exits = Vec::new();
+        for _ in 0..self.u8()? {
+            let position = self.pos()?;
+            let room_id = self.id()?;
+            let exit_position = self.pos()?;
+            let dialogue_id = self.opt_id()?;
+            exits.push(bf::ExitInstance {
+                position,
+                exit: bf::Exit {
+                    room_id,
+                    position: exit_position,
+                    effect: bf::Transition::None,
+                },
+                transition: None,
+                dialogue_id,
+            });
+        }
+        let mut endings = Vec::new();
+        for _ in 0..self.u8()? {
+            let id = self.id()?;
+            let position = self.pos()?;
+            endings.push(bf::Instance { position, id });
+        }
+        let walls = match self.u8()? {
+            0 => None,
+            _ => {
+                let mut walls = Vec::new();
+                for _ in 0..self.u8()? {
+                    walls.push(self.id()?);
+                }
+                Some(walls)
+            }
+        };
+        let room = bf::Room {
+            id,
+            palette_id,
+            name,
+            tiles,
+            items,
+            exits,
+            endings,
+            walls,
+        };
+        Some((room, avatar))
+    }
+
+    fn tokens(&mut self) -> Option<Vec<bs::Token>> {
+        let n = self.u16()?;
+        let mut tokens = Vec::new();
+        for _ in 0..n {
+            tokens.push(self.token()?);
+        }
+        Some(tokens)
+    }
+
+    fn token(&mut self) -> Option<bs::Token> {
+        let token = match self.u8()? {
+            0 => bs::Token::OpenTag(self.tag()?),
+            1 => bs::Token::CloseTag(self.tag()?),
+            2 => bs::Token::Word(self.str()?),
+            _ => return None,
+        };
+        Some(token)
+    }
+
+    fn tag(&mut self) -> Option<bs::Tag> {
+        use bs::Tag::*;
+        let tag = match self.u8()? {
+            0 => Br,
+            1 => Pg,
+            2 => Eff(self.effect()?),
+            3 => End,
+            4 => Say(self.expr()?),
+            5 => DrwT(self.str()?),
+            6 => DrwS(self.str()?),
+            7 => DrwI(self.str()?),
+            8 => Pal(self.str()?),
+            9 => Ava(self.str()?),
+            10 => Exit(self.str()?, self.u8()?, self.u8()?),
+            11 => Set(self.str()?, self.expr()?),
+            12 => Unknown(self.str()?, self.str()?),
+            _ => return None,
+        };
+        Some(tag)
+    }
+
+    fn effect(&mut self) -> Option<bs::TextEffect> {
+        use bs::TextEffect::*;
+        let effect = match self.u8()? {
+            0 => None,
+            1 => Wavy,
+            2 => Shaky,
+            3 => Rainbow,
+            4 => Color(self.u8()?),
+            _ => return Option::None,
+        };
+        Some(effect)
+    }
+
+    fn expr(&mut self) -> Option<bs::Expr> {
+        let expr = match self.u8()? {
+            0 => bs::Expr::SimpleExpr(self.simple_expr()?),
+            1 => {
+                let op = self.bin_op()?;
+                bs::Expr::BinOp(op, self.simple_expr()?, self.simple_expr()?)
+            }
+            _ => return None,
+        };
+        Some(expr)
+    }
+
+    fn bin_op(&mut self) -> Option<bs::BinOp> {
+        use bs::BinOp::*;
+        let op = match self.u8()? {
+            0 => Mul,
+            1 => Div,
+            2 => Add,
+            3 => Sub,
+            4 => Lt,
+            5 => Gt,
+            6 => Lte,
+            7 => Gte,
+            8 => Eq,
+            _ => return None,
+        };
+        Some(op)
+    }
+
+    fn simple_expr(&mut self) -> Option<bs::SimpleExpr> {
+        let expr = match self.u8()? {
+            0 => bs::SimpleExpr::Var(self.str()?),
+            1 => bs::SimpleExpr::Item(self.str()?),
+            2 => bs::SimpleExpr::Val(self.val()?),
+            _ => return None,
+        };
+        Some(expr)
+    }
+
+    fn val(&mut self) -> Option<bs::Val> {
+        let val = match self.u8()? {
+            0 => bs::Val::Undef,
+            1 => {
+                let raw = self.bytes(2)?;
+                bs::Val::I(i16::from_le_bytes([raw[0], raw[1]]))
+            }
+            2 => bs::Val::S(self.str()?),
+            3 => {
+                let raw = self.bytes(4)?;
+                bs::Val::F(f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]))
+            }
+            _ => return None,
+        };
+        Some(val)
+    }
+}
@@ -0,0 +1,363 @@
+use crate::*;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bitsy_script as bs;
+use firefly_rust as ff;
+
+/// The data file where the block counters are saved, see [`Counters::load`].
+const COUNTERS_FILE: &str = "counters";
+
+/// How many times each `{sequence}`, `{cycle}`, and `{shuffle}` block was shown.
+///
+/// ```text
+/// {sequence
+///   - Hi!
+///   - Hi again.
+///   - Go away.
+/// }
+/// ```
+///
+/// Like in Bitsy, a block advances each time its dialog is shown, across conversations:
+///
+/// * a sequence shows the options in order and then repeats the last one;
+/// * a cycle shows the options in order and then starts over;
+/// * a shuffle shows all options in a random order, then reshuffles them and starts over.
+///
+/// A block is identified by the dialog it belongs to and a hash of its source,
+/// so the identifiers don't depend on the order in which the game is parsed
+/// or on which branches of the dialog were shown. The counters are saved
+/// under these identifiers, so they survive restarting the game.
+#[derive(Default)]
+pub struct Counters {
+    counts: BTreeMap<String, Counter>,
+    /// If the counters changed since they were last saved.
+    changed: bool,
+}
+
+#[derive(Default, Clone, PartialEq, Eq, Debug)]
+struct Counter {
+    /// How many times a sequence was shown,
+    /// or the position in the current round of a cycle or a shuffle.
+    count: u16,
+    /// The order of options in the current round of a shuffle.
+    order: Vec<u16>,
+}
+
+impl Counters {
+    /// Read the counters saved by [`Counters::save`] in a previous run.
+    ///
+    /// Empty if nothing is saved yet or the file is corrupted.
+    pub fn load() -> Self {
+        let Some(file) = ff::load_file_buf(COUNTERS_FILE) else {
+            return Self::default();
+        };
+        Self::decode(file.data()).unwrap_or_default()
+    }
+
+    /// Write the counters into the data file if they changed since the last save.
+    pub fn save(&mut self) {
+        if !self.changed {
+            return;
+        }
+        self.changed = false;
+        ff::dump_file(COUNTERS_FILE, &self.encode());
+    }
+
+    /// Serialize the counters as little-endian values.
+    ///
+    /// For each block: the identifier, the count, and the shuffle order.
+    fn encode(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (key, counter) in &self.counts {
+            data.extend_from_slice(&(key.len() as u16).to_le_bytes());
+            data.extend_from_slice(key.as_bytes());
+            data.extend_from_slice(&counter.count.to_le_bytes());
+            data.extend_from_slice(&(counter.order.len() as u16).to_le_bytes());
+            for idx in &counter.order {
+                data.extend_from_slice(&idx.to_le_bytes());
+            }
+        }
+        data
+    }
+
+    fn decode(mut data: &[u8]) -> Option<Self> {
+        fn u16(data: &mut &[u8]) -> Option<u16> {
+            let (val, rest) = data.split_first_chunk()?;
+            *data = rest;
+            Some(u16::from_le_bytes(*val))
+        }
+        let mut counts = BTreeMap::new();
+        while !data.is_empty() {
+            let len = usize::from(u16(&mut data)?);
+            let (key, rest) = data.split_at_checked(len)?;
+            data = rest;
+            let key = String::from(core::str::from_utf8(key).ok()?);
+            let count = u16(&mut data)?;
+            let mut order = Vec::new();
+            for _ in 0..u16(&mut data)? {
+                order.push(u16(&mut data)?);
+            }
+            counts.insert(key, Counter { count, order });
+        }
+        Some(Self {
+            counts,
+            changed: false,
+        })
+    }
+}
+
+impl HeapSize for Counters {
+    fn heap_size(&self) -> usize {
+        let counters = self.counts.iter().map(|(key, counter)| {
+            let order = counter.order.capacity() * size_of::<u16>();
+            key.heap_size() + size_of::<(String, Counter)>() + order
+        });
+        counters.sum()
+    }
+}
+
+/// Picks options of the blocks in a single dialog.
+pub struct Blocks<'a> {
+    counters: &'a mut Counters,
+    /// Identifies the dialog (or ending) the blocks belong to.
+    script_id: &'a str,
+    /// The source of randomness for shuffle blocks.
+    rng: &'a mut Rng,
+    /// Hashes of the blocks already shown in this dialog, see [`Dialog::seen`].
+    ///
+    /// Used to tell apart identical blocks in the same dialog.
+    seen: &'a mut Vec<u32>,
+}
+
+enum BlockKind {
+    Sequence,
+    Cycle,
+    Shuffle,
+}
+
+impl<'a> Blocks<'a> {
+    pub fn new(
+        counters: &'a mut Counters,
+        script_id: &'a str,
+        rng: &'a mut Rng,
+        seen: &'a mut Vec<u32>,
+    ) -> Self {
+        Self {
+            counters,
+            script_id,
+            rng,
+            seen,
+        }
+    }
+
+    /// If the tag is a block, pick the option to show and advance the counter.
+    ///
+    /// Returns the tokens of the picked option.
+    pub fn resolve(&mut self, name: &str, args: &str) -> Option<Vec<bs::Token>> {
+        if !name.starts_with(['s', 'c']) {
+            return None;
+        }
+        let text = unfold_tag(name, args);
+        let (name, body) = text.split_once(['\n', '\r'])?;
+        let kind = match name.trim_ascii() {
+            "sequence" => BlockKind::Sequence,
+            "cycle" => BlockKind::Cycle,
+            "shuffle" => BlockKind::Shuffle,
+            _ => return None,
+        };
+        let options = split_options(body);
+        if options.is_empty() {
+            return None;
+        }
+
+        let hash = fnv_hash(&text);
+        let n_seen = self.seen.iter().filter(|h| **h == hash).count();
+        self.seen.push(hash);
+        let key = format!("{} {hash:08x} {n_seen}", self.script_id);
+        let counter = self.counters.counts.entry(key).or_default();
+        self.counters.changed = true;
+
+        let n_options = options.len();
+        let count = usize::from(counter.count);
+        let idx = match kind {
+            BlockKind::Sequence => count.min(n_options - 1),
+            BlockKind::Cycle => count % n_options,
+            BlockKind::Shuffle => {
+                let mut pos = count % n_options;
+                // Reshuffle after all options are shown or if the block has changed.
+                if pos == 0 || counter.order.len() != n_options {
+                    counter.order = shuffled(n_options, self.rng);
+                    pos = 0;
+                }
+                counter.count = pos as u16;
+                usize::from(counter.order[pos])
+            }
+        };
+        counter.count = match kind {
+            BlockKind::Sequence => counter.count.saturating_add(1),
+            _ => ((usize::from(counter.count) + 1) % n_options) as u16,
+        };
+        let option = fold_tags(&options[idx]);
+        Some(bs::Tokenizer::new(&option).collect())
+    }
+}
+
+/// A random permutation of option indices.
+fn shuffled(n: usize, rng: &mut Rng) -> Vec<u16> {
+    let mut order: Vec<u16> = (0..n as u16).collect();
+    for i in (1..n).rev() {
+        order.swap(i, rng.below(i + 1));
+    }
+    order
+}
+
+/// 32-bit FNV-1a hash, stable across builds and platforms.
+fn fnv_hash(text: &str) -> u32 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in text.bytes() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEQUENCE: &str = "{sequence\n  - one\n  - two\n}";
+    const CYCLE: &str = "{cycle\n  - one\n  - two\n}";
+    const SHUFFLE: &str = "{shuffle\n  - one\n  - two\n  - three\n}";
+
+    /// Get the name and the arguments of the block tag, like the dialog builder does.
+    fn tag(source: &str) -> (String, String) {
+        let source = fold_tags(source);
+        let mut tokens = bs::Tokenizer::new(&source);
+        match tokens.next() {
+            Some(bs::Token::OpenTag(bs::Tag::Unknown(name, args))) => (name, args),
+            token => panic!("unexpected token: {token:?}"),
+        }
+    }
+
+    fn text(tokens: Option<Vec<bs::Token>>) -> String {
+        let mut text = String::new();
+        for token in tokens.unwrap() {
+            match token {
+                bs::Token::Word(word) => text.push_str(&word),
+                token => panic!("unexpected token: {token:?}"),
+            }
+        }
+        text
+    }
+
+    /// Show the dialog with the given block the given number of times.
+    fn show(source: &str, times: usize, counters: &mut Counters, rng: &mut Rng) -> Vec<String> {
+        let (name, args) = tag(source);
+        let mut shown = Vec::new();
+        for _ in 0..times {
+            let mut seen = Vec::new();
+            let mut blocks = Blocks::new(counters, "DLG 0", rng, &mut seen);
+            shown.push(text(blocks.resolve(&name, &args)));
+        }
+        shown
+    }
+
+    #[test]
+    fn sequence_sticks_on_last_option() {
+        let mut counters = Counters::default();
+        let mut rng = Rng::new(1);
+        let shown = show(SEQUENCE, 4, &mut counters, &mut rng);
+        assert_eq!(shown, ["one", "two", "two", "two"]);
+    }
+
+    #[test]
+    fn cycle_wraps() {
+        let mut counters = Counters::default();
+        let mut rng = Rng::new(1);
+        let shown = show(CYCLE, 5, &mut counters, &mut rng);
+        assert_eq!(shown, ["one", "two", "one", "two", "one"]);
+    }
+
+    #[test]
+    fn shuffle_shows_each_option_once_per_round() {
+        let mut counters = Counters::default();
+        let mut rng = Rng::new(42);
+        let shown = show(SHUFFLE, 30, &mut counters, &mut rng);
+        for round in shown.chunks(3) {
+            let mut round = round.to_vec();
+            round.sort();
+            assert_eq!(round, ["one", "three", "two"]);
+        }
+        // The order changes between rounds.
+        assert!(shown.chunks(3).any(|round| round != &shown[..3]));
+    }
+
+    #[test]
+    fn keys_are_stable_across_dialogs() {
+        let mut counters = Counters::default();
+        let mut rng = Rng::new(1);
+        show(SEQUENCE, 1, &mut counters, &mut rng);
+        let keys: Vec<String> = counters.counts.keys().cloned().collect();
+        show(SEQUENCE, 2, &mut counters, &mut rng);
+        let counts: Vec<(&String, u16)> =
+            counters.counts.iter().map(|(k, c)| (k, c.count)).collect();
+        assert_eq!(counts, [(&keys[0], 3)]);
+    }
+
+    #[test]
+    fn identical_blocks_in_one_dialog_are_counted_separately() {
+        let mut counters = Counters::default();
+        let mut rng = Rng::new(1);
+        let mut seen = Vec::new();
+        let (name, args) = tag(SEQUENCE);
+        let mut blocks = Blocks::new(&mut counters, "DLG 0", &mut rng, &mut seen);
+        assert_eq!(text(blocks.resolve(&name, &args)), "one");
+        // The rest of the dialog after a choice keeps the blocks already seen.
+        let mut blocks = Blocks::new(&mut counters, "DLG 0", &mut rng, &mut seen);
+        assert_eq!(text(blocks.resolve(&name, &args)), "one");
+        assert_eq!(counters.counts.len(), 2);
+    }
+
+    #[test]
+    fn other_tags_are_not_blocks() {
+        let mut counters = Counters::default();
+        let mut rng = Rng::new(1);
+        let mut seen = Vec::new();
+        let mut blocks = Blocks::new(&mut counters, "DLG 0", &mut rng, &mut seen);
+        let (name, args) = tag("{choice\n  - one\n}");
+        assert!(blocks.resolve(&name, &args).is_none());
+        let (name, args) = tag("{sequence}");
+        assert!(blocks.resolve(&name, &args).is_none());
+    }
+
+    #[test]
+    fn counters_round_trip() {
+        let mut counters = Counters::default();
+        let mut rng = Rng::new(7);
+        show(SEQUENCE, 2, &mut counters, &mut rng);
+        show(SHUFFLE, 1, &mut counters, &mut rng);
+        assert!(counters.changed);
+        let data = counters.encode();
+        let loaded = Counters::decode(&data).unwrap();
+        assert_eq!(loaded.counts, counters.counts);
+        assert!(!loaded.changed);
+
+        // The shuffle continues the same round after loading.
+        let mut loaded = loaded;
+        let before = show(SHUFFLE, 2, &mut counters, &mut Rng::new(9));
+        let after = show(SHUFFLE, 2, &mut loaded, &mut Rng::new(9));
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn corrupted_counters_are_rejected() {
+        let mut counters = Counters::default();
+        let mut rng = Rng::new(7);
+        show(SEQUENCE, 1, &mut counters, &mut rng);
+        let data = counters.encode();
+        assert!(Counters::decode(&data[..data.len() - 1]).is_none());
+        assert!(Counters::decode(&[]).unwrap().counts.is_empty());
+    }
+}
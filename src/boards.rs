@@ -0,0 +1,27 @@
+use crate::*;
+use bitsy_script::Val;
+use firefly_rust as ff;
+
+/// How many updates the game gets per second.
+const UPDATES_PER_SECOND: u32 = 60;
+
+/// Submit scores for all boards that should be updated at the given moment.
+pub fn submit_scores(state: &State, when: Submit) {
+    for rule in &state.config.boards {
+        if rule.when != when {
+            continue;
+        }
+        let score = match &rule.score {
+            Score::Var(name) => match state.script_state.vars.get(name) {
+                Val::I(v) => *v,
+                Val::F(v) => *v as i16,
+                _ => continue,
+            },
+            Score::Time => {
+                let tenths = state.play_frames / (UPDATES_PER_SECOND / 10);
+                -i16::try_from(tenths).unwrap_or(i16::MAX)
+            }
+        };
+        ff::add_score(ff::Peer::COMBINED, rule.board, score);
+    }
+}
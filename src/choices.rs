@@ -0,0 +1,142 @@
+use crate::*;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bitsy_script as bs;
+use firefly_rust as ff;
+
+/// A list of options shown at the end of a dialog, like in the "dialog choices" hack.
+///
+/// ```text
+/// Where to?
+/// {choice
+///   - The forest
+///     It's dark in here.{forest = 1}
+///   - The lake
+///     The water is cold.
+/// }
+/// ```
+///
+/// The script of the selected option is shown next,
+/// followed by the rest of the dialog after the choice.
+pub struct Choice {
+    pub options: Vec<ChoiceOption>,
+    /// The tokens of the dialog after the choice.
+    pub rest: Vec<bs::Token>,
+    /// The index of the selected option.
+    pub cursor: usize,
+}
+
+pub struct ChoiceOption {
+    pub label: String,
+    pub tokens: Vec<bs::Token>,
+}
+
+/// Parse the `{choice ...}` tag.
+///
+/// Returns `None` if the tag isn't a choice or has no options.
+pub fn parse_choice(name: &str, args: &str) -> Option<Vec<ChoiceOption>> {
+    if !name.starts_with("choice") {
+        return None;
+    }
+    let text = unfold_tag(name, args);
+    let (name, text) = text.split_once(['\n', '\r'])?;
+    if name.trim_ascii() != "choice" {
+        return None;
+    }
+    let mut options = Vec::new();
+    for option in split_options(text) {
+        let (label, body) = option.split_once('\n').unwrap_or((&option, ""));
+        options.push(ChoiceOption {
+            label: label.into(),
+            tokens: bs::Tokenizer::new(&fold_tags(body)).collect(),
+        });
+    }
+    if options.is_empty() {
+        return None;
+    }
+    Some(options)
+}
+
+/// Move the cursor with up and down, select the option with left or right.
+pub fn handle_choice_pad(state: &mut State, pressed: ff::DPad4) {
+    let Some(page) = state.dialog.current_page() else {
+        return;
+    };
+    let Some(choice) = &mut page.choice else {
+        return;
+    };
+    match pressed {
+        ff::DPad4::None => return,
+        ff::DPad4::Up => choice.cursor = choice.cursor.saturating_sub(1),
+        ff::DPad4::Down => choice.cursor = (choice.cursor + 1).min(choice.options.len() - 1),
+        ff::DPad4::Left | ff::DPad4::Right => {
+            let Some(choice) = page.choice.take() else {
+                return;
+            };
+            select_option(state, choice);
+            return;
+        }
+    }
+    page.redraw();
+}
+
+/// Continue the dialog with the script of the selected option.
+fn select_option(state: &mut State, choice: Choice) {
+    let Some(option) = choice.options.into_iter().nth(choice.cursor) else {
+        return;
+    };
+    push_text(state, option.label);
+    let tokens = option.tokens.into_iter().chain(choice.rest);
+    continue_dialog(state, tokens);
+}
+
+/// Draw the options with the cursor in the dialog box.
+///
+/// The list is scrolled to keep the selected option visible.
+pub fn draw_choice(choice: &Choice, font: &ff::Font, point: ff::Point) {
+    const VISIBLE: usize = 2;
+    let char_width = i32::from(font.char_width());
+    let char_height = i32::from(font.char_height());
+    let offset = choice.cursor.saturating_sub(VISIBLE - 1);
+    let options = choice.options.iter().enumerate().skip(offset);
+    for ((i, option), row) in options.take(VISIBLE).zip(0..) {
+        let point = point + ff::Point::new(0, row * char_height);
+        if i == choice.cursor {
+            ff::draw_text(">", font, point, COLOR_DIALOG_TEXT);
+        }
+        let point = point + ff::Point::new(char_width * 2, 0);
+        ff::draw_text(&option.label, font, point, COLOR_DIALOG_TEXT);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example from the [`Choice`] docs.
+    const EXAMPLE: &str = "Where to?
+{choice
+  - The forest
+    It's dark in here.{forest = 1}
+  - The lake
+    The water is cold.
+}";
+
+    #[test]
+    fn parse_documented_example() {
+        let text = fold_tags(EXAMPLE);
+        let tag = bs::Tokenizer::new(&text).find_map(|token| match token {
+            bs::Token::OpenTag(bs::Tag::Unknown(name, args)) => Some((name, args)),
+            _ => None,
+        });
+        let (name, args) = tag.unwrap();
+        let options = parse_choice(&name, &args).unwrap();
+        let labels: Vec<&str> = options.iter().map(|o| o.label.as_str()).collect();
+        assert_eq!(labels, ["The forest", "The lake"]);
+        let expected: Vec<bs::Token> =
+            bs::Tokenizer::new("It's dark in here.{forest = 1}").collect();
+        assert_eq!(options[0].tokens, expected);
+        let expected: Vec<bs::Token> = bs::Tokenizer::new("The water is cold.").collect();
+        assert_eq!(options[1].tokens, expected);
+    }
+}
@@ -0,0 +1,305 @@
+use crate::*;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bitsy_file as bf;
+use bitsy_script as bs;
+
+/// The maximum tile coordinate in a room.
+const MAX_POS: u8 = 15;
+
+/// A change of the game world requested by a dialog script.
+///
+/// Supported script functions:
+///
+/// * `{sprite "id" x y "room"}` moves the sprite to the given position.
+/// * `{item "id" x y "room"}` puts a new item at the given position.
+/// * `{drawAt "SPR,id,x,y,room"}` and `{drawAt "ITM,id,x,y,room"}`
+///   do the same as the two functions above.
+/// * `{eraseAt "SPR,id,x,y,room"}` removes the sprite from the world.
+/// * `{eraseAt "ITM,id,x,y,room"}` removes the item at the given position.
+///
+/// The room is optional and defaults to the current room.
+///
+/// Script functions registered in [`Functions`] can also change the world.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    PlaceSprite(String, bf::Position, Option<String>),
+    RemoveSprite(String),
+    AddItem(String, bf::Position, Option<String>),
+    RemoveItem(String, bf::Position, Option<String>),
+    /// Call the script function with the given name and arguments.
+    Call(String, Vec<bs::Val>),
+}
+
+/// Wraps dialog tokens and extracts world commands from them.
+///
+/// Sequence, cycle, and shuffle blocks are replaced by the tokens of the picked option.
+/// A dialog choice stops the dialog, the tokens after it are stored in the choice.
+/// A call of a script function that needs the script state pauses the tokens
+/// until the function is evaluated, see [`CommandTokens::take_call`].
+/// All other tokens are passed into the interpreter as is.
+pub struct CommandTokens<'a, T: Iterator<Item = bs::Token>> {
+    tokens: T,
+    commands: &'a mut Vec<Command>,
+    choice: &'a mut Option<Choice>,
+    blocks: Blocks<'a>,
+    functions: &'a Functions,
+    /// Tokens to pass before the rest, in reverse order.
+    pending: Vec<bs::Token>,
+    /// The script function to evaluate before continuing, with its arguments.
+    call: Option<(EvalFn, Vec<bs::Val>)>,
+}
+
+impl<'a, T: Iterator<Item = bs::Token>> CommandTokens<'a, T> {
+    pub fn new(
+        tokens: T,
+        commands: &'a mut Vec<Command>,
+        choice: &'a mut Option<Choice>,
+        blocks: Blocks<'a>,
+        functions: &'a Functions,
+    ) -> Self {
+        Self {
+            tokens,
+            commands,
+            choice,
+            blocks,
+            functions,
+            pending: Vec::new(),
+            call: None,
+        }
+    }
+
+    /// Get the script function call that paused the tokens.
+    pub fn take_call(&mut self) -> Option<(EvalFn, Vec<bs::Val>)> {
+        self.call.take()
+    }
+
+    /// Pass the given tokens (like the result of a function call) before the rest.
+    pub fn insert(&mut self, tokens: Vec<bs::Token>) {
+        self.pending.extend(tokens.into_iter().rev());
+    }
+}
+
+impl<T: Iterator<Item = bs::Token>> Iterator for CommandTokens<'_, T> {
+    type Item = bs::Token;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let token = match self.pending.pop() {
+                Some(token) => token,
+                None => self.tokens.next()?,
+            };
+            if let bs::Token::OpenTag(bs::Tag::Unknown(name, args)) = &token {
+                if let Some(command) = parse_command(name, args) {
+                    self.commands.push(command);
+                    continue;
+                }
+                if let Some(tokens) = self.blocks.resolve(name, args) {
+                    self.insert(tokens);
+                    continue;
+                }
+                if let Some(options) = parse_choice(name, args) {
+                    let pending = self.pending.drain(..).rev();
+                    *self.choice = Some(Choice {
+                        options,
+                        rest: pending.chain(self.tokens.by_ref()).collect(),
+                        cursor: 0,
+                    });
+                    return None;
+                }
+                if let Some(call) = self.functions.parse_call(name, args) {
+                    if call.function.apply.is_some() {
+                        let command = Command::Call(call.name.clone(), call.args.clone());
+                        self.commands.push(command);
+                    }
+                    if let Some(eval) = call.function.eval {
+                        self.call = Some((eval, call.args));
+                        return None;
+                    }
+                    continue;
+                }
+            }
+            return Some(token);
+        }
+    }
+}
+
+fn parse_command(name: &str, args: &str) -> Option<Command> {
+    match name {
+        "sprite" => parse_kind_command("SPR", &split_args(args), false),
+        "item" => parse_kind_command("ITM", &split_args(args), false),
+        "drawAt" => {
+            let args = split_packed_args(args);
+            let (kind, args) = args.split_first()?;
+            parse_kind_command(kind, args, false)
+        }
+        "eraseAt" => {
+            let args = split_packed_args(args);
+            let (kind, args) = args.split_first()?;
+            parse_kind_command(kind, args, true)
+        }
+        _ => None,
+    }
+}
+
+/// Parse arguments in the form of `id,x,y,room` for the given object kind.
+fn parse_kind_command(kind: &str, args: &[&str], erase: bool) -> Option<Command> {
+    let [id, x, y, rest @ ..] = args else {
+        return None;
+    };
+    let x: u8 = x.parse().ok()?;
+    let y: u8 = y.parse().ok()?;
+    let pos = bf::Position {
+        x: x.min(MAX_POS),
+        y: y.min(MAX_POS),
+    };
+    let id = id.to_string();
+    let room = rest.first().map(|room| room.to_string());
+    let command = match (kind, erase) {
+        ("SPR", false) => Command::PlaceSprite(id, pos, room),
+        ("SPR", true) => Command::RemoveSprite(id),
+        ("ITM", false) => Command::AddItem(id, pos, room),
+        ("ITM", true) => Command::RemoveItem(id, pos, room),
+        _ => return None,
+    };
+    Some(command)
+}
+
+/// Split function arguments separated by spaces or commas and remove quotes.
+///
+/// A quoted argument is kept whole, even if it has spaces or commas in it.
+pub fn split_args(args: &str) -> Vec<&str> {
+    let mut result = Vec::new();
+    let mut rest = args;
+    loop {
+        rest = rest.trim_start_matches([',', ' ']);
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let end = quoted.find('"').unwrap_or(quoted.len());
+            result.push(&quoted[..end]);
+            rest = quoted.get(end + 1..).unwrap_or_default();
+        } else {
+            let end = rest.find([',', ' ']).unwrap_or(rest.len());
+            result.push(&rest[..end]);
+            rest = &rest[end..];
+        }
+    }
+    result
+}
+
+/// Split the arguments of `drawAt` and `eraseAt`.
+///
+/// All arguments are usually packed into a single quoted string: `"SPR,id,x,y,room"`.
+fn split_packed_args(args: &str) -> Vec<&str> {
+    match split_args(args).as_slice() {
+        [packed] => packed.split(',').map(str::trim_ascii).collect(),
+        args => args.to_vec(),
+    }
+}
+
+/// Apply the world change requested by a dialog script.
+pub fn apply_command(state: &mut State, command: Command) {
+    if let Command::PlaceSprite(_, _, Some(room))
+    | Command::AddItem(_, _, Some(room))
+    | Command::RemoveItem(_, _, Some(room)) = &command
+        && state.index.room(room).is_none()
+    {
+        finish_loading(state);
+    }
+    let current_room = state.game.rooms[state.room].id.clone();
+    match command {
+        Command::PlaceSprite(id, pos, room) => {
+            if id == "A" {
+                if let Some(room) = room
+                    && room != current_room
+                {
+                    // Don't move the avatar within the current room
+                    // if the target room doesn't exist.
+                    let Some(room_idx) = state.index.room(&room) else {
+                        return;
+                    };
+                    state.set_room(room_idx);
+                }
+                state.set_pos(pos);
+                state.room_dirty = true;
+                return;
+            }
+            let room = room.unwrap_or(current_room);
+            let Some(idx) = state.index.sprite(&id) else {
+                return;
+            };
+            let room_idx = state.index.room(&room);
+            state.index.place_sprite(idx, room_idx);
+            let sprite = &mut state.game.sprites[idx];
+            sprite.room_id = Some(room);
+            sprite.position = Some(pos);
+        }
+        Command::RemoveSprite(id) => {
+            let Some(idx) = state.index.sprite(&id) else {
+                return;
+            };
+            state.index.place_sprite(idx, None);
+            let sprite = &mut state.game.sprites[idx];
+            sprite.room_id = None;
+            sprite.position = None;
+        }
+        Command::AddItem(id, position, room) => {
+            let room = room.unwrap_or(current_room);
+            let Some(idx) = state.index.room(&room) else {
+                return;
+            };
+            state.index.push_item(idx, &id);
+            state.game.rooms[idx]
+                .items
+                .push(bf::Instance { position, id });
+        }
+        Command::Call(name, args) => {
+            let Some(function) = state.functions.get(&name) else {
+                return;
+            };
+            if let Some(apply) = function.apply {
+                state.calls.push((apply, args));
+            }
+        }
+        Command::RemoveItem(id, pos, room) => {
+            let room = room.unwrap_or(current_room);
+            let Some(idx) = state.index.room(&room) else {
+                return;
+            };
+            let items = &mut state.game.rooms[idx].items;
+            // Removed back to front to keep the indices of the remaining items.
+            for i in (0..items.len()).rev() {
+                if items[i].id == id && items[i].position == pos {
+                    items.remove(i);
+                    state.index.remove_item(idx, i);
+                }
+            }
+        }
+    }
+    state.room_dirty = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_args_keeps_quoted_strings_whole() {
+        assert_eq!(split_args(r#""my room",3, 4"#), ["my room", "3", "4"]);
+        assert_eq!(split_args(r#"key 1 2 "a, b""#), ["key", "1", "2", "a, b"]);
+        assert_eq!(split_args(r#""unclosed"#), ["unclosed"]);
+        assert_eq!(split_args(" , "), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn parse_command_with_quoted_room() {
+        let pos = bf::Position { x: 3, y: 4 };
+        let command = parse_command("sprite", r#""A" 3 4 "my room""#);
+        let expected = Command::PlaceSprite("A".into(), pos, Some("my room".into()));
+        assert_eq!(command, Some(expected));
+        let command = parse_command("drawAt", r#""ITM,key,3,4""#);
+        assert_eq!(command, Some(Command::AddItem("key".into(), pos, None)));
+    }
+}
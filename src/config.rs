@@ -0,0 +1,307 @@
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use firefly_rust as ff;
+
+/// Optional settings for running a specific game, loaded from the "config" file.
+///
+/// The file is a list of lines, each line is a setting name followed
+/// by space-separated arguments. Empty lines and lines starting with `#` are ignored.
+///
+/// ```text
+/// # award badge 1 when reaching the ending "0"
+/// badge 1 ending 0
+/// # award badge 2 when picking up the item "key"
+/// badge 2 item key
+/// # award badge 3 when the variable "score" reaches 10
+/// badge 3 var score 10
+/// # submit the variable "score" to board 1 when reaching an ending
+/// board 1 var score ending
+/// # submit the variable "coins" to board 2 when entering a room
+/// board 2 var coins room
+/// # submit the play time in tenths of a second to board 3 when reaching an ending
+/// board 3 time
+/// # each connected peer controls its own avatar
+/// multiplayer coop
+/// # each player sees only their own dialogs
+/// dialogs personal
+/// # peers take turns controlling the avatar, passing the turn after each dialog
+/// multiplayer hotseat
+/// turns dialog
+/// # periodically log how much memory the interpreter uses
+/// memory log
+/// # enable the developer menu for warping to rooms and editing variables
+/// debug on
+/// # reveal a dialog letter every 2 updates, 0 shows whole pages at once
+/// text delay 2
+/// # draw the dialog box over the room, at the top if the avatar is in the lower half
+/// dialog box overlay
+/// # show the sprite or the item that started a dialog next to the text
+/// portraits on
+/// # use a fixed random seed, so that every run of the game is the same
+/// seed 42
+/// ```
+pub struct Config {
+    pub badges: Vec<BadgeRule>,
+    pub boards: Vec<BoardRule>,
+    pub multiplayer: Multiplayer,
+    /// In multiplayer, if all players see the same dialog.
+    pub shared_dialogs: bool,
+    /// In hot-seat multiplayer, when the turn passes to the next peer.
+    pub turns: Turns,
+    /// If the memory usage should be logged, for fitting big games on the device.
+    pub log_memory: bool,
+    /// If the developer menu is available. Must be off in released games.
+    pub debug: bool,
+    /// How many updates to wait before revealing the next letter of a dialog.
+    ///
+    /// Zero disables the typewriter animation.
+    pub text_delay: u16,
+    /// Where the dialog box is shown.
+    pub dialog_box: DialogBox,
+    /// If the speaking sprite (or item) is shown in the dialog box.
+    pub portraits: bool,
+    /// The seed for the random number generator. Random if not set.
+    pub seed: Option<u32>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            badges: Vec::new(),
+            boards: Vec::new(),
+            multiplayer: Multiplayer::Off,
+            shared_dialogs: true,
+            turns: Turns::Room,
+            log_memory: false,
+            debug: false,
+            text_delay: 3,
+            dialog_box: DialogBox::Below,
+            portraits: false,
+            seed: None,
+        }
+    }
+}
+
+/// How the game is played when multiple peers are connected.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Multiplayer {
+    /// All peers control the same avatar.
+    Off,
+    /// Each peer controls its own avatar.
+    Coop,
+    /// Peers take turns controlling the avatar.
+    Hotseat,
+}
+
+/// When the turn passes to the next peer in hot-seat multiplayer.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum Turns {
+    /// When the player enters a new room.
+    Room,
+    /// When a dialog is closed.
+    Dialog,
+}
+
+/// Where the dialog box is shown, except the title and endings shown in the middle.
+#[derive(PartialEq, Eq, Clone, Copy)]
+pub enum DialogBox {
+    /// Below the room, using the whole screen width.
+    Below,
+    /// Over the room, like in Bitsy.
+    ///
+    /// The box is at the bottom of the room unless it would hide the avatar.
+    Overlay,
+}
+
+/// When to award a Firefly badge.
+pub struct BadgeRule {
+    pub badge: ff::Badge,
+    pub trigger: Trigger,
+}
+
+pub enum Trigger {
+    /// The ending with the given ID is reached.
+    Ending(String),
+    /// The item with the given ID is picked up.
+    Item(String),
+    /// The variable with the given name is greater than or equal to the value.
+    Var(String, i16),
+}
+
+/// What and when to submit to a Firefly board.
+pub struct BoardRule {
+    pub board: ff::Board,
+    pub score: Score,
+    pub when: Submit,
+}
+
+pub enum Score {
+    /// The value of the variable with the given name.
+    Var(String),
+    /// The play time since the game start in tenths of a second, for speedruns.
+    ///
+    /// Submitted as a negative value, so that lower scores rank higher.
+    /// Fits runs up to 54 minutes, longer runs get the lowest score.
+    Time,
+}
+
+#[derive(PartialEq, Eq)]
+pub enum Submit {
+    /// When an ending is reached.
+    Ending,
+    /// When the player enters a new room.
+    Room,
+}
+
+impl Config {
+    pub fn load() -> Self {
+        let Some(raw) = ff::load_file_buf("config") else {
+            return Self::default();
+        };
+        let Ok(raw) = alloc::str::from_utf8(raw.data()) else {
+            ff::log_error("config is not valid UTF-8");
+            return Self::default();
+        };
+        Self::parse(raw)
+    }
+
+    /// Parse the settings from the text in the format of the "config" file.
+    ///
+    /// Invalid lines are logged and ignored.
+    pub fn parse(raw: &str) -> Self {
+        let mut config = Self::default();
+        for line in raw.lines() {
+            let line = line.trim_ascii();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let args: Vec<&str> = line.split_ascii_whitespace().collect();
+            let ok = match args.as_slice() {
+                ["badge", args @ ..] => config.parse_badge(args),
+                ["board", args @ ..] => config.parse_board(args),
+                ["multiplayer", "off"] => {
+                    config.multiplayer = Multiplayer::Off;
+                    true
+                }
+                ["multiplayer", "coop"] => {
+                    config.multiplayer = Multiplayer::Coop;
+                    true
+                }
+                ["multiplayer", "hotseat"] => {
+                    config.multiplayer = Multiplayer::Hotseat;
+                    true
+                }
+                ["turns", "room"] => {
+                    config.turns = Turns::Room;
+                    true
+                }
+                ["turns", "dialog"] => {
+                    config.turns = Turns::Dialog;
+                    true
+                }
+                ["dialogs", "shared"] => {
+                    config.shared_dialogs = true;
+                    true
+                }
+                ["dialogs", "personal"] => {
+                    config.shared_dialogs = false;
+                    true
+                }
+                ["memory", "log"] => {
+                    config.log_memory = true;
+                    true
+                }
+                ["debug", "on"] => {
+                    config.debug = true;
+                    true
+                }
+                ["debug", "off"] => {
+                    config.debug = false;
+                    true
+                }
+                ["dialog", "box", "below"] => {
+                    config.dialog_box = DialogBox::Below;
+                    true
+                }
+                ["dialog", "box", "overlay"] => {
+                    config.dialog_box = DialogBox::Overlay;
+                    true
+                }
+                ["portraits", "on"] => {
+                    config.portraits = true;
+                    true
+                }
+                ["portraits", "off"] => {
+                    config.portraits = false;
+                    true
+                }
+                ["seed", seed] => match seed.parse() {
+                    Ok(seed) => {
+                        config.seed = Some(seed);
+                        true
+                    }
+                    Err(_) => false,
+                },
+                ["text", "delay", delay] => match delay.parse() {
+                    Ok(delay) => {
+                        config.text_delay = delay;
+                        true
+                    }
+                    Err(_) => false,
+                },
+                _ => false,
+            };
+            if !ok {
+                ff::log_error("invalid config line:");
+                ff::log_error(line);
+            }
+        }
+        config
+    }
+
+    fn parse_badge(&mut self, args: &[&str]) -> bool {
+        let [badge, args @ ..] = args else {
+            return false;
+        };
+        let Ok(badge) = badge.parse() else {
+            return false;
+        };
+        let trigger = match args {
+            ["ending", id] => Trigger::Ending(id.to_string()),
+            ["item", id] => Trigger::Item(id.to_string()),
+            ["var", name, val] => {
+                let Ok(val) = val.parse() else {
+                    return false;
+                };
+                Trigger::Var(name.to_string(), val)
+            }
+            _ => return false,
+        };
+        self.badges.push(BadgeRule {
+            badge: ff::Badge(badge),
+            trigger,
+        });
+        true
+    }
+
+    fn parse_board(&mut self, args: &[&str]) -> bool {
+        let [board, args @ ..] = args else {
+            return false;
+        };
+        let Ok(board) = board.parse() else {
+            return false;
+        };
+        let (score, when) = match args {
+            ["time"] => (Score::Time, Submit::Ending),
+            ["var", name, "ending"] => (Score::Var(name.to_string()), Submit::Ending),
+            ["var", name, "room"] => (Score::Var(name.to_string()), Submit::Room),
+            _ => return false,
+        };
+        self.boards.push(BoardRule {
+            board: ff::Board(board),
+            score,
+            when,
+        });
+        true
+    }
+}
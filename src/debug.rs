@@ -0,0 +1,115 @@
+use crate::*;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bitsy_script::Val;
+use firefly_rust as ff;
+
+/// How long (in updates) the buttons must be held to toggle the debug info.
+const TOGGLE_DELAY: u32 = 60;
+/// The width of the empty space on each side of the room.
+const MARGIN_WIDTH: i32 = (ff::WIDTH - 8 * 16) / 2;
+/// The height of the room, the dialog box is below it.
+const MARGIN_HEIGHT: i32 = 128;
+/// How many characters of a line fit into the margin.
+const LINE_LEN: usize = 9;
+const LINE_HEIGHT: i32 = 10;
+
+/// Check if the button combination toggling the debug info is held.
+///
+/// The buttons are ignored for moving the avatar while they are held together.
+pub fn is_debug_combo(buttons: ff::Buttons) -> bool {
+    buttons.w && buttons.e
+}
+
+/// Toggle the debug info if the W and E buttons are held together for a second.
+///
+/// The debug info is shown only on the local device, so the local input is used
+/// (or the input provided by the host game, see [`read_input`]).
+/// It doesn't affect the game state, so devices in multiplayer don't diverge.
+pub fn handle_debug_toggle(state: &mut State) {
+    let me = ff::get_me();
+    let Some(peer) = ff::get_peers().iter().find(|peer| *peer == me) else {
+        return;
+    };
+    let (_, buttons) = read_input(state, peer);
+    if !is_debug_combo(buttons) {
+        state.debug_held_for = 0;
+        return;
+    }
+    state.debug_held_for += 1;
+    if state.debug_held_for == TOGGLE_DELAY {
+        state.show_debug = !state.show_debug;
+        state.room_dirty = true;
+    }
+}
+
+/// Show the script state in the empty space on the sides of the room.
+///
+/// The lines start in the right margin and continue in the left one.
+pub fn draw_debug(state: &State) {
+    if !state.show_debug {
+        return;
+    }
+    let style = ff::Style::solid(COLOR_BG);
+    let size = ff::Size::new(MARGIN_WIDTH, MARGIN_HEIGHT);
+    ff::draw_rect(ff::Point::new(0, 0), size, style);
+    let right_x = ff::WIDTH - MARGIN_WIDTH;
+    ff::draw_rect(ff::Point::new(right_x, 0), size, style);
+
+    // The top of the left margin is occupied by the hot-seat turn indicator.
+    let left_y = if state.turn_peers.is_empty() { 0 } else { 2 };
+    let per_margin = (MARGIN_HEIGHT / LINE_HEIGHT) as usize;
+    let right = (0..per_margin).map(|i| (right_x + 2, i));
+    let left = (left_y..per_margin).map(|i| (2, i));
+    let mut cells = right.chain(left);
+
+    let font = state.font.as_font();
+    // The tile color of the current palette, contrast to the background.
+    let color = ff::Color::new(2);
+    for line in debug_lines(state) {
+        let Some((x, i)) = cells.next() else {
+            break;
+        };
+        let line = match line.char_indices().nth(LINE_LEN) {
+            Some((end, _)) => &line[..end],
+            None => &line,
+        };
+        let y = LINE_HEIGHT * (i as i32 + 1);
+        ff::draw_text(line, &font, ff::Point::new(x, y), color);
+    }
+}
+
+fn debug_lines(state: &State) -> Vec<String> {
+    let script = &state.script_state;
+    let mut lines = Vec::from([
+        "ROOM".to_string(),
+        script.room.clone(),
+        format!("POS {},{}", script.pos_x, script.pos_y),
+        "PAL".to_string(),
+        script.palette.clone(),
+        format!("PAGES {}", state.dialog.n_pages()),
+    ]);
+    // Variables created by scripts are not listed
+    // because the script state doesn't allow iterating over variables.
+    lines.push("VARS".to_string());
+    for var in &state.game.variables {
+        let val = format_val(script.vars.get(&var.id));
+        lines.push(format!("{}={val}", var.id));
+    }
+    lines.push("ITEMS".to_string());
+    for (idx, count) in collected_items(state) {
+        lines.push(format!("{}x{count}", state.game.items[idx].id));
+    }
+    lines
+}
+
+/// Show the value of a script variable.
+pub fn format_val(val: &Val) -> String {
+    match val {
+        Val::Undef => "-".to_string(),
+        Val::I(val) => val.to_string(),
+        Val::S(val) => val.clone(),
+        Val::F(val) => val.to_string(),
+    }
+}
@@ -0,0 +1,206 @@
+use crate::*;
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bitsy_script::Val;
+use firefly_rust as ff;
+
+/// The index of the developer menu in the app menu.
+pub const MENU_DEV: u8 = 2;
+/// The vertical space occupied by each row.
+const ROW_HEIGHT: i32 = 12;
+/// Where the list of rows starts.
+const LIST_Y: i32 = 24;
+/// How many rows fit on the screen.
+const PAGE_SIZE: usize = 10;
+/// The number of rows before the variables, see [`Row`].
+const N_FIXED_ROWS: usize = 5;
+
+/// The state of the developer menu, enabled by `debug on` in the config.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DevMenu {
+    /// The selected row.
+    cursor: usize,
+    /// If the list of all rooms is shown instead of the main list.
+    picking_room: bool,
+    /// The room to warp to.
+    room: usize,
+    /// The position in the room to warp to.
+    x: u8,
+    y: u8,
+}
+
+/// A row of the main list of the developer menu.
+enum Row {
+    Room,
+    X,
+    Y,
+    Warp,
+    Noclip,
+    /// The variable with the given index in the game.
+    Var(usize),
+    Close,
+}
+
+/// Open the developer menu with the current room and position preselected.
+pub fn open_dev_menu(state: &mut State) {
+    // All rooms must be parsed to be listed.
+    finish_loading(state);
+    let pos = state.pos();
+    state.overlay = Overlay::Dev(DevMenu {
+        cursor: 0,
+        picking_room: false,
+        room: state.room,
+        x: pos.x,
+        y: pos.y,
+    });
+}
+
+/// Select rows with up and down, change the selected row with left and right.
+pub fn handle_dev_pad(state: &mut State, pressed: ff::DPad4) {
+    let Overlay::Dev(mut menu) = state.overlay else {
+        return;
+    };
+    if menu.picking_room {
+        let n_rooms = state.game.rooms.len();
+        match pressed {
+            ff::DPad4::None => return,
+            ff::DPad4::Up => menu.room = menu.room.saturating_sub(1),
+            ff::DPad4::Down if menu.room + 1 < n_rooms => menu.room += 1,
+            ff::DPad4::Down => {}
+            ff::DPad4::Left | ff::DPad4::Right => menu.picking_room = false,
+        }
+        state.overlay = Overlay::Dev(menu);
+        return;
+    }
+
+    let n_rows = N_FIXED_ROWS + state.game.variables.len() + 1;
+    let delta: i8 = match pressed {
+        ff::DPad4::None => return,
+        ff::DPad4::Up => {
+            menu.cursor = menu.cursor.saturating_sub(1);
+            0
+        }
+        ff::DPad4::Down => {
+            menu.cursor = (menu.cursor + 1).min(n_rows - 1);
+            0
+        }
+        ff::DPad4::Left => -1,
+        ff::DPad4::Right => 1,
+    };
+    if delta != 0 {
+        match get_row(state, menu.cursor) {
+            Row::Room => menu.picking_room = true,
+            Row::X => menu.x = move_coord(menu.x, delta),
+            Row::Y => menu.y = move_coord(menu.y, delta),
+            Row::Warp => {
+                warp(state, menu);
+                return;
+            }
+            Row::Noclip => state.noclip = !state.noclip,
+            Row::Var(idx) => edit_var(state, idx, delta.into()),
+            Row::Close => {
+                state.close_overlay();
+                return;
+            }
+        }
+    }
+    state.overlay = Overlay::Dev(menu);
+}
+
+pub fn draw_dev_menu(state: &State, menu: DevMenu) {
+    ff::clear_screen(COLOR_DIALOG_BOX);
+    let font = state.font.as_font();
+    let char_width = i32::from(font.char_width());
+    let (title, rows, cursor) = if menu.picking_room {
+        let rooms = state.game.rooms.iter();
+        let rows = rooms.map(|room| match &room.name {
+            Some(name) => format!("{} {name}", room.id),
+            None => room.id.clone(),
+        });
+        ("ROOMS", rows.collect(), menu.room)
+    } else {
+        ("DEBUG", row_labels(state, menu), menu.cursor)
+    };
+    let x = (ff::WIDTH - char_width * title.len() as i32) / 2;
+    ff::draw_text(title, &font, ff::Point::new(x, 10), COLOR_DIALOG_TEXT);
+
+    // Scroll the list so that the selected row is always visible.
+    let offset = cursor.saturating_sub(PAGE_SIZE - 1);
+    let mut y = LIST_Y;
+    for (i, row) in rows.iter().enumerate().skip(offset).take(PAGE_SIZE) {
+        if i == cursor {
+            ff::draw_text(">", &font, ff::Point::new(8, y), COLOR_DIALOG_TEXT);
+        }
+        ff::draw_text(row, &font, ff::Point::new(20, y), COLOR_DIALOG_TEXT);
+        y += ROW_HEIGHT;
+    }
+
+    const HINT: &str = "UP/DOWN: SELECT  LEFT/RIGHT: CHANGE";
+    let x = (ff::WIDTH - char_width * HINT.len() as i32) / 2;
+    ff::draw_text(HINT, &font, ff::Point::new(x, 154), COLOR_DIALOG_TEXT);
+}
+
+fn row_labels(state: &State, menu: DevMenu) -> Vec<String> {
+    let room = match state.game.rooms.get(menu.room) {
+        Some(room) => room.id.as_str(),
+        None => "-",
+    };
+    let noclip = if state.noclip { "ON" } else { "OFF" };
+    let mut labels = Vec::from([
+        format!("ROOM {room}"),
+        format!("X {}", menu.x),
+        format!("Y {}", menu.y),
+        "WARP".to_string(),
+        format!("NOCLIP {noclip}"),
+    ]);
+    for var in &state.game.variables {
+        let val = format_val(state.script_state.vars.get(&var.id));
+        labels.push(format!("{} = {val}", var.id));
+    }
+    labels.push("CLOSE".to_string());
+    labels
+}
+
+fn get_row(state: &State, cursor: usize) -> Row {
+    match cursor {
+        0 => Row::Room,
+        1 => Row::X,
+        2 => Row::Y,
+        3 => Row::Warp,
+        4 => Row::Noclip,
+        i if i - N_FIXED_ROWS < state.game.variables.len() => Row::Var(i - N_FIXED_ROWS),
+        _ => Row::Close,
+    }
+}
+
+fn move_coord(coord: u8, delta: i8) -> u8 {
+    coord.saturating_add_signed(delta).min(15)
+}
+
+/// Move the avatar to the room and position selected in the menu.
+fn warp(state: &mut State, menu: DevMenu) {
+    if menu.room >= state.game.rooms.len() {
+        return;
+    }
+    state.set_pos(bitsy_file::Position {
+        x: menu.x,
+        y: menu.y,
+    });
+    state.set_room(menu.room);
+    state.close_overlay();
+}
+
+/// Increment or decrement the number in the variable.
+///
+/// Strings cannot be edited with buttons, so they are left as is.
+fn edit_var(state: &mut State, idx: usize, delta: i16) {
+    let name = state.game.variables[idx].id.clone();
+    let val = match state.script_state.vars.get(&name) {
+        Val::I(val) => Val::I(val.saturating_add(delta)),
+        Val::F(val) => Val::F(val + f32::from(delta)),
+        Val::Undef => Val::I(delta),
+        Val::S(_) => return,
+    };
+    state.script_state.vars.set(name, val);
+}
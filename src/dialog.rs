@@ -1,4 +1,5 @@
 use crate::*;
+use alloc::string::String;
 use alloc::vec::Vec;
 use bitsy_script as bs;
 use firefly_rust as ff;
@@ -7,36 +8,108 @@ use firefly_rust as ff;
 pub struct Dialog {
     pub pages: Vec<Page>,
     pub center: bool,
+    /// The sprite or the item that started the dialog, if any.
+    pub speaker: Option<Speaker>,
+    /// Identifies the script of the dialog for block counters, see [`Counters`].
+    pub script_id: String,
+    /// Hashes of the blocks shown so far, see [`Blocks`].
+    ///
+    /// Kept for the rest of the dialog shown after a choice.
+    pub seen: Vec<u32>,
+    /// World changes requested by the dialog script.
+    ///
+    /// Applied (and drained) when the dialog is shown.
+    pub commands: Vec<Command>,
 }
 
 impl Dialog {
-    pub fn new(dialog: &str, state: &mut bs::State, char_width: u8, char_height: u8) -> Self {
+    pub fn new(
+        dialog: &str,
+        state: &mut bs::State,
+        layout: TextLayout,
+        blocks: Blocks<'_>,
+        functions: &Functions,
+    ) -> Self {
+        let dialog = fold_tags(strip_triple_quotes(dialog));
+        let tokens = bs::Tokenizer::new(&dialog);
+        Self::from_tokens(tokens, state, layout, blocks, functions)
+    }
+
+    /// Build the dialog from an already tokenized script.
+    pub fn from_tokens<T>(
+        tokens: T,
+        state: &mut bs::State,
+        layout: TextLayout,
+        blocks: Blocks<'_>,
+        functions: &Functions,
+    ) -> Self
+    where
+        T: Iterator<Item = bs::Token>,
+    {
         let builder = DialogBuilder {
-            char_width,
-            char_height,
+            layout,
             ..Default::default()
         };
-        builder.build(dialog, state)
+        builder.build(tokens, state, blocks, functions)
     }
 
     pub fn n_pages(&self) -> usize {
         self.pages.len()
     }
 
+    /// Check if the current page is a list of options to select from.
+    pub fn has_choice(&self) -> bool {
+        match self.pages.first() {
+            Some(page) => page.choice.is_some(),
+            None => false,
+        }
+    }
+
+    /// Check if the current page is (at least partially) rendered on the screen.
+    pub fn is_started(&self) -> bool {
+        match self.pages.first() {
+            Some(page) => page.started,
+            None => false,
+        }
+    }
+
     pub fn current_page(&mut self) -> Option<&mut Page> {
         self.pages.first_mut()
     }
 
-    pub fn next_page(&mut self) {
-        let Some(page) = self.pages.first_mut() else {
-            return;
-        };
+    /// Render the current page again.
+    pub fn redraw(&mut self) {
+        if let Some(page) = self.current_page() {
+            page.redraw();
+        }
+    }
+
+    /// Remove the current page, even if it's not fully rendered yet.
+    pub fn skip_page(&mut self) -> Option<Page> {
+        if self.pages.is_empty() {
+            return None;
+        }
+        Some(self.pages.remove(0))
+    }
+
+    /// Finish rendering the current page or, if it's already rendered, remove it.
+    pub fn next_page(&mut self) -> Option<Page> {
+        let page = self.pages.first_mut()?;
         if !page.fast && !page.all_rendered() {
             page.fast = true;
-        } else {
-            self.pages.remove(0);
+            return None;
         }
+        Some(self.pages.remove(0))
+    }
+}
+
+/// Remove triple quotes around a multi-line dialog.
+pub fn strip_triple_quotes(dialog: &str) -> &str {
+    const TRIPLE_QUOTE: &str = r#"""""#;
+    if let Some(new_dialog) = dialog.strip_prefix(TRIPLE_QUOTE) {
+        return new_dialog.strip_suffix(TRIPLE_QUOTE).unwrap_or(dialog);
     }
+    dialog
 }
 
 pub struct Page {
@@ -47,54 +120,131 @@ pub struct Page {
     pub started: bool,
     /// If true, stop the words animation and render the whole page in one go.
     pub fast: bool,
+    /// If true, render again all the words that were already rendered.
+    ///
+    /// Used when something (like an overlay) was drawn over the dialog box.
+    pub redraw: bool,
+    /// The options to select from, shown instead of the words.
+    pub choice: Option<Choice>,
 }
 
 impl Page {
     pub fn all_rendered(&self) -> bool {
+        self.words.iter().all(Word::is_rendered)
+    }
+
+    /// The text of the page without text effects and images.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
         for word in &self.words {
-            if !word.rendered {
-                return false;
+            match &word.word {
+                bs::Word::Text(word, _) => text.push_str(word),
+                bs::Word::LineBreak if !text.ends_with(' ') => text.push(' '),
+                _ => {}
             }
         }
-        true
+        text.trim_ascii().into()
+    }
+
+    /// Render the page again, without restarting the words animation.
+    pub fn redraw(&mut self) {
+        self.started = false;
+        self.redraw = true;
     }
 }
 
 pub struct Word {
     pub word: bs::Word,
     pub point: ff::Point,
-    pub rendered: bool,
+    /// How many letters of the word are already revealed on the screen.
+    pub shown: usize,
+}
+
+impl Word {
+    /// The number of letters to reveal before the word is fully rendered.
+    ///
+    /// Words that aren't text (like line breaks) have nothing to reveal.
+    pub fn n_letters(&self) -> usize {
+        match &self.word {
+            bs::Word::Text(text, _) => text.chars().count(),
+            _ => 0,
+        }
+    }
+
+    pub fn is_rendered(&self) -> bool {
+        self.shown >= self.n_letters()
+    }
+
+    /// The part of the text that is already revealed.
+    pub fn shown_text(&self) -> &str {
+        let bs::Word::Text(text, _) = &self.word else {
+            return "";
+        };
+        match text.char_indices().nth(self.shown) {
+            Some((end, _)) => &text[..end],
+            None => text,
+        }
+    }
+}
+
+/// The game object that started a dialog, as a handle from [`Index`].
+#[derive(Clone, PartialEq, Eq)]
+pub enum Speaker {
+    Sprite(usize),
+    Item(usize),
+}
+
+/// How much text fits into the dialog box.
+#[derive(Clone, Copy, Default)]
+pub struct TextLayout {
+    pub char_width: u8,
+    pub char_height: u8,
+    /// The width of a line, in pixels.
+    pub width: usize,
 }
 
 #[derive(Default)]
 struct DialogBuilder {
     pages: Vec<Page>,
     words: Vec<Word>,
-    char_width: u8,
-    char_height: u8,
+    layout: TextLayout,
     offset_x: usize,
     offset_y: usize,
 }
 
 impl DialogBuilder {
-    pub fn build(mut self, dialog: &str, state: &mut bs::State) -> Dialog {
-        const TRIPLE_QUOTE: &str = r#"""""#;
-        let box_width = firefly_rust::WIDTH as usize;
-        let box_height = usize::from(self.char_height) * 2;
-
-        // Remove triple quotes around the dialog
-        let mut dialog = dialog;
-        if let Some(new_dialog) = dialog.strip_prefix(TRIPLE_QUOTE) {
-            dialog = new_dialog.strip_suffix(TRIPLE_QUOTE).unwrap_or(dialog);
-        }
+    pub fn build<T>(
+        mut self,
+        tokens: T,
+        state: &mut bs::State,
+        blocks: Blocks<'_>,
+        functions: &Functions,
+    ) -> Dialog
+    where
+        T: Iterator<Item = bs::Token>,
+    {
+        let box_width = self.layout.width;
+        let box_height = usize::from(self.layout.char_height) * 2;
 
-        let tokenizer = bs::Tokenizer::new(dialog);
-        let interpreter = bs::Interpreter {
-            tokens: tokenizer,
-            state,
-        };
+        let mut commands = Vec::new();
+        let mut choice = None;
+        let tokens = CommandTokens::new(tokens, &mut commands, &mut choice, blocks, functions);
+        let mut interpreter = bs::Interpreter { tokens, state };
 
-        for word in interpreter {
+        loop {
+            let Some(word) = interpreter.next() else {
+                // The tokens are paused to evaluate a script function.
+                let Some((eval, args)) = interpreter.tokens.take_call() else {
+                    break;
+                };
+                if let Some(text) = eval(interpreter.state, &args) {
+                    let text = fold_tags(&text);
+                    interpreter
+                        .tokens
+                        .insert(bs::Tokenizer::new(&text).collect());
+                }
+                continue;
+            };
             use bs::Word::*;
             match word {
                 LineBreak => {
@@ -111,7 +261,7 @@ impl DialogBuilder {
                 }
                 w => {
                     let n_chars: usize = if let Text(t, _) = &w { t.len() } else { 8 };
-                    let word_width = n_chars * usize::from(self.char_width);
+                    let word_width = n_chars * usize::from(self.layout.char_width);
                     if self.offset_x + word_width > box_width {
                         self = self.flush_line();
                         if self.offset_y >= box_height {
@@ -122,7 +272,7 @@ impl DialogBuilder {
                     self.words.push(Word {
                         word: w,
                         point,
-                        rendered: false,
+                        shown: 0,
                     });
                     self.offset_x += word_width;
                 }
@@ -134,18 +284,34 @@ impl DialogBuilder {
                 words: self.words,
                 started: false,
                 fast: false,
+                redraw: false,
+                choice: None,
+            });
+        }
+        // The options are shown on a separate page after the text.
+        if let Some(choice) = choice {
+            self.pages.push(Page {
+                words: Vec::new(),
+                started: false,
+                fast: false,
+                redraw: false,
+                choice: Some(choice),
             });
         }
         Dialog {
             pages: self.pages,
             center: false,
+            speaker: None,
+            script_id: String::new(),
+            seen: Vec::new(),
+            commands,
         }
     }
 
     fn flush_line(mut self) -> Self {
         if self.offset_x != 0 {
             self.offset_x = 0;
-            self.offset_y += usize::from(self.char_height);
+            self.offset_y += usize::from(self.layout.char_height);
         }
         self
     }
@@ -157,6 +323,8 @@ impl DialogBuilder {
             words: self.words,
             started: false,
             fast: false,
+            redraw: false,
+            choice: None,
         });
         self.words = Vec::new();
         self
@@ -0,0 +1,226 @@
+use crate::*;
+use alloc::boxed::Box;
+use alloc::string::{String, ToString};
+use bitsy_script::Val;
+use core::fmt;
+use firefly_rust as ff;
+
+/// A running Bitsy game.
+///
+/// The standalone interpreter calls it from the Firefly callbacks,
+/// and a bigger Firefly game can do the same to run a Bitsy game as one of its scenes:
+///
+/// ```ignore
+/// let game = ff::load_file_buf("forest").unwrap();
+/// let font = ff::load_file_buf("font").unwrap();
+/// let mut engine = Engine::new(game, font, Config::default()).unwrap();
+///
+/// // In update:
+/// engine.update(Input::DPad(dpad));
+/// for event in engine.events() {
+///     if let Event::EndingReached(_) = event {
+///         // Go back to the main game.
+///     }
+/// }
+///
+/// // In render:
+/// engine.render();
+/// ```
+pub struct Engine {
+    pub(crate) state: State,
+}
+
+/// Where the engine reads the input of the active player from.
+///
+/// The input provided by the host game is ignored in multiplayer,
+/// where each player controls their avatar from their device.
+#[derive(Default, Clone, Copy)]
+pub enum Input {
+    /// Read the pad and the buttons of the device, like the standalone interpreter does.
+    #[default]
+    Device,
+    /// Use the direction provided by the host game, with no buttons pressed.
+    DPad(ff::DPad4),
+    /// Use the direction and the buttons provided by the host game.
+    ///
+    /// Like on the device, the buttons move the avatar too,
+    /// and holding W and E together toggles the debug info.
+    Pad(ff::DPad4, ff::Buttons),
+}
+
+/// Why the game cannot be started, see [`Engine::new`].
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoadError {
+    /// The game is neither a precompiled game nor valid UTF-8 text.
+    NotText,
+    /// The precompiled game is made by a different version of `tools/bitsy-bin`.
+    WrongVersion,
+    /// The precompiled game is truncated or corrupted.
+    InvalidBinary,
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let msg = match self {
+            Self::NotText => "the game is not valid UTF-8",
+            Self::WrongVersion => "the game is precompiled by a different version of the tool",
+            Self::InvalidBinary => "invalid precompiled game",
+        };
+        f.write_str(msg)
+    }
+}
+
+/// Something that happened in the game, reported by [`Engine::events`].
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    /// The avatar entered the room with the given ID, including the starting room.
+    RoomEntered(String),
+    /// The avatar picked up the item with the given ID.
+    ItemPicked(String),
+    /// The dialog with the given ID was started by a sprite, an item, or an exit.
+    DialogShown(String),
+    /// The last page of a dialog (including the title and endings) was closed.
+    DialogClosed,
+    /// The avatar reached the ending with the given ID.
+    ///
+    /// The game is over when the ending dialog is closed, see [`Engine::is_ended`].
+    EndingReached(String),
+}
+
+impl Engine {
+    /// Start the game from the text of a Bitsy game or a precompiled game.
+    ///
+    /// The game is parsed in chunks on the first updates, see [`Engine::is_loading`].
+    /// The game can be passed as a [`ff::FileBuf`] without copying.
+    /// It's freed as soon as it's not needed to keep the peak memory usage low.
+    pub fn new(
+        game: impl Into<Box<[u8]>>,
+        font: ff::FileBuf,
+        config: Config,
+    ) -> Result<Self, LoadError> {
+        let state = load_state(game.into(), font, config)?;
+        Ok(Self { state })
+    }
+
+    /// Start the game from the "main", "font", and (optional) "config" files of the ROM.
+    ///
+    /// # Panics
+    ///
+    /// If the game or the font file is missing or the game cannot be loaded.
+    pub fn from_rom() -> Self {
+        let Some(game) = ff::load_file_buf("main") else {
+            panic!("game not found")
+        };
+        let Some(font) = ff::load_file_buf("font") else {
+            panic!("font not found")
+        };
+        match Self::new(game, font, Config::load()) {
+            Ok(engine) => engine,
+            Err(err) => panic!("{err}"),
+        }
+    }
+
+    /// Add the inventory, the dialog history, and (if enabled) the developer menu to the app menu.
+    ///
+    /// The items use the menu indices 1 to 3, see [`Engine::handle_menu`].
+    /// A host game with its own use of the app menu can instead open the overlays
+    /// with [`Engine::open_inventory`] and [`Engine::open_history`].
+    pub fn add_menu_items(&self) {
+        ff::add_menu_item(MENU_INVENTORY, "Inventory");
+        ff::add_menu_item(MENU_HISTORY, "History");
+        if self.state.config.debug {
+            ff::add_menu_item(MENU_DEV, "Debug");
+        }
+    }
+
+    /// Advance the game by one frame.
+    ///
+    /// Clears the events of the previous update.
+    pub fn update(&mut self, input: Input) {
+        self.state.events.clear();
+        self.state.input = input;
+        update_state(&mut self.state);
+        let calls = core::mem::take(&mut self.state.calls);
+        for (apply, args) in calls {
+            apply(self, &args);
+        }
+        activate_local_player(&mut self.state);
+        self.state.counters.save();
+    }
+
+    /// Draw the game on the screen.
+    ///
+    /// Only the changed parts are redrawn, so the screen must not be touched by the host game
+    /// while the engine is running. Call [`Engine::redraw`] after drawing something else.
+    pub fn render(&mut self) {
+        render_room(&mut self.state);
+    }
+
+    /// Draw everything on the next render, including the current dialog page.
+    pub fn redraw(&mut self) {
+        self.state.room_dirty = true;
+        self.state.drawn_overlay = None;
+        self.state.dialog.redraw();
+    }
+
+    /// Handle selection of an item added by [`Engine::add_menu_items`].
+    pub fn handle_menu(&mut self, index: u8) {
+        handle_menu_item(&mut self.state, index);
+    }
+
+    /// Show the items the player has, until any button other than up or down is pressed.
+    ///
+    /// Does nothing while the game is loading and in multiplayer.
+    pub fn open_inventory(&mut self) {
+        handle_menu_item(&mut self.state, MENU_INVENTORY);
+    }
+
+    /// Show the dialog pages the player has already seen.
+    ///
+    /// Does nothing while the game is loading and in multiplayer.
+    pub fn open_history(&mut self) {
+        handle_menu_item(&mut self.state, MENU_HISTORY);
+    }
+
+    /// What happened in the game on the last update.
+    pub fn events(&self) -> &[Event] {
+        &self.state.events
+    }
+
+    /// Check if the game is still being parsed and cannot be played yet.
+    pub fn is_loading(&self) -> bool {
+        self.state.is_loading()
+    }
+
+    /// Check if the game reached an ending and the ending dialog is closed.
+    pub fn is_ended(&self) -> bool {
+        self.state.script_state.end && self.state.dialog.n_pages() == 0
+    }
+
+    /// The ID of the current room.
+    pub fn room(&self) -> &str {
+        &self.state.script_state.room
+    }
+
+    /// The value of the script variable with the given name.
+    ///
+    /// [`Val::Undef`] if the variable is not set.
+    pub fn var(&self, name: &str) -> &Val {
+        self.state.script_state.vars.get(name)
+    }
+
+    /// Change the value of the script variable with the given name.
+    pub fn set_var(&mut self, name: &str, val: Val) {
+        self.state.script_state.vars.set(name.to_string(), val);
+    }
+
+    /// How many items with the given ID the player has.
+    pub fn item_count(&self, item_id: &str) -> u16 {
+        self.state.script_state.inventory.get(&item_id.to_string())
+    }
+
+    /// Make a script function available to dialogs, replacing the one with the same name.
+    pub fn register_function(&mut self, name: &str, function: Function) {
+        self.state.functions.register(name, function);
+    }
+}
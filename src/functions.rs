@@ -0,0 +1,130 @@
+use crate::*;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bitsy_script::{self as bs, Val};
+use firefly_rust as ff;
+
+/// The type of a script function parameter.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Param {
+    Int,
+    Str,
+}
+
+/// Evaluates a script function call while the dialog is interpreted.
+pub type EvalFn = fn(&mut bs::State, &[Val]) -> Option<String>;
+/// Applies a script function call after the dialog is shown.
+pub type ApplyFn = fn(&mut Engine, &[Val]);
+
+/// A script function implemented in Rust, called from dialogs as `{name arg1 arg2}`.
+///
+/// Arguments are separated by spaces or commas, strings may be quoted.
+/// Calls with a wrong number of arguments or wrong argument types are logged and ignored.
+#[derive(Clone, Copy)]
+pub struct Function {
+    pub params: &'static [Param],
+    /// Called while the dialog is interpreted, in order with the rest of the script,
+    /// so it can read and change variables and the inventory.
+    ///
+    /// The returned text (if any) is shown in place of the call.
+    pub eval: Option<EvalFn>,
+    /// Called at the end of the update in which the dialog is shown,
+    /// after the world commands of the dialog are applied.
+    ///
+    /// Can change the game through the [`Engine`] API.
+    pub apply: Option<ApplyFn>,
+}
+
+/// Script functions available to dialogs, by name.
+#[derive(Default)]
+pub struct Functions {
+    functions: BTreeMap<String, Function>,
+}
+
+impl Functions {
+    /// The functions available to all games.
+    ///
+    /// * `{giveBadge 1}` earns the Firefly badge with the given ID.
+    /// * `{setFlag "name"}` sets the variable with the given name to 1.
+    pub fn builtin() -> Self {
+        let mut functions = Self::default();
+        functions.register(
+            "setFlag",
+            Function {
+                params: &[Param::Str],
+                eval: Some(set_flag),
+                apply: None,
+            },
+        );
+        functions.register(
+            "giveBadge",
+            Function {
+                params: &[Param::Int],
+                eval: None,
+                apply: Some(give_badge),
+            },
+        );
+        functions
+    }
+
+    /// Add a new script function or replace the one with the same name.
+    pub fn register(&mut self, name: &str, function: Function) {
+        self.functions.insert(name.to_string(), function);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+
+    /// If the tag calls a registered function, parse the arguments.
+    pub fn parse_call(&self, name: &str, args: &str) -> Option<Call> {
+        let function = self.get(name)?;
+        let args = split_args(args);
+        if args.len() != function.params.len() {
+            ff::log_error("wrong number of arguments for script function:");
+            ff::log_error(name);
+            return None;
+        }
+        let mut vals = Vec::with_capacity(args.len());
+        for (arg, param) in args.iter().zip(function.params) {
+            let val = match param {
+                Param::Int => arg.parse().ok().map(Val::I),
+                Param::Str => Some(Val::S(arg.to_string())),
+            };
+            let Some(val) = val else {
+                ff::log_error("wrong argument type for script function:");
+                ff::log_error(name);
+                return None;
+            };
+            vals.push(val);
+        }
+        Some(Call {
+            function: *function,
+            name: name.to_string(),
+            args: vals,
+        })
+    }
+}
+
+/// A call of a script function found in a dialog.
+pub struct Call {
+    pub function: Function,
+    pub name: String,
+    pub args: Vec<Val>,
+}
+
+fn set_flag(state: &mut bs::State, args: &[Val]) -> Option<String> {
+    if let [Val::S(name)] = args {
+        state.vars.set(name.clone(), Val::I(1));
+    }
+    None
+}
+
+fn give_badge(engine: &mut Engine, args: &[Val]) {
+    if let [Val::I(badge)] = args
+        && let Ok(badge) = u8::try_from(*badge)
+    {
+        award(&mut engine.state, ff::Badge(badge));
+    }
+}
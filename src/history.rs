@@ -0,0 +1,192 @@
+use crate::*;
+use alloc::collections::VecDeque;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use firefly_rust as ff;
+
+/// The index of the dialog history in the app menu.
+pub const MENU_HISTORY: u8 = 3;
+/// How many pages are remembered. Older pages are forgotten.
+const MAX_ENTRIES: usize = 64;
+/// The vertical space occupied by each line.
+const LINE_HEIGHT: i32 = 10;
+/// Where the list of lines starts.
+const LIST_Y: i32 = 24;
+/// How many lines fit on the screen.
+const PAGE_SIZE: usize = 13;
+/// The horizontal padding on both sides of the lines.
+const MARGIN_X: i32 = 8;
+
+/// The log of dialog pages the player has already seen.
+///
+/// The pages are wrapped into lines when they are added,
+/// so that the history can be drawn and scrolled without allocating.
+#[derive(Default)]
+pub struct History {
+    /// The lines of all remembered entries, with an empty line between entries.
+    lines: Vec<String>,
+    /// How many lines each entry takes, including the empty line before it.
+    sizes: VecDeque<usize>,
+}
+
+impl History {
+    /// Remember a wrapped entry, forgetting the oldest one if there are too many.
+    fn push_lines(&mut self, mut lines: Vec<String>) {
+        if lines.is_empty() {
+            return;
+        }
+        if self.sizes.len() == MAX_ENTRIES
+            && let Some(size) = self.sizes.pop_front()
+        {
+            self.lines.drain(..size);
+            // The next entry becomes the first one and loses the empty line before it.
+            if let Some(first) = self.sizes.front_mut() {
+                self.lines.remove(0);
+                *first -= 1;
+            }
+        }
+        let mut size = lines.len();
+        if !self.lines.is_empty() {
+            self.lines.push(String::new());
+            size += 1;
+        }
+        self.lines.append(&mut lines);
+        self.sizes.push_back(size);
+    }
+
+    pub fn lines(&self) -> &[String] {
+        &self.lines
+    }
+}
+
+/// Remember the dismissed page of the current dialog.
+pub fn push_page(state: &mut State, page: &Page) {
+    push_text(state, page.text());
+}
+
+/// Remember a line of the dialog that isn't a page, like a selected choice.
+///
+/// The text is prefixed with the name of the speaker and wrapped to fit the screen.
+pub fn push_text(state: &mut State, text: String) {
+    if text.is_empty() {
+        return;
+    }
+    let text = match &state.dialog.speaker {
+        Some(speaker) => format!("{}: {text}", speaker_name(state, speaker)),
+        None => text,
+    };
+    let font = state.font.as_font();
+    let char_width = usize::from(font.char_width()).max(1);
+    let line_len = (ff::WIDTH - MARGIN_X * 2) as usize / char_width;
+    let mut lines = Vec::new();
+    wrap(&text, line_len, &mut lines);
+    state.history.push_lines(lines);
+}
+
+/// Open the dialog history scrolled to the most recent pages.
+pub fn open_history(state: &mut State) {
+    let n_lines = state.history.lines().len();
+    state.overlay = Overlay::History(n_lines.saturating_sub(PAGE_SIZE));
+}
+
+/// Scroll the history with up and down, close it with anything else.
+pub fn handle_history_pad(state: &mut State, pressed: ff::DPad4) {
+    let Overlay::History(offset) = state.overlay else {
+        return;
+    };
+    let n_lines = state.history.lines().len();
+    let offset = match pressed {
+        ff::DPad4::None => return,
+        ff::DPad4::Up => offset.saturating_sub(1),
+        ff::DPad4::Down if offset + PAGE_SIZE < n_lines => offset + 1,
+        ff::DPad4::Down => offset,
+        _ => {
+            state.close_overlay();
+            return;
+        }
+    };
+    state.overlay = Overlay::History(offset);
+}
+
+pub fn draw_history(state: &State, offset: usize) {
+    let lines = state.history.lines();
+    let font = state.font.as_font();
+    let mut y = LIST_Y;
+    for line in draw_list_overlay(state, "HISTORY", lines, offset, PAGE_SIZE) {
+        ff::draw_text(line, &font, ff::Point::new(MARGIN_X, y), COLOR_DIALOG_TEXT);
+        y += LINE_HEIGHT;
+    }
+}
+
+/// The name of the sprite or the item if it has one, the ID otherwise.
+fn speaker_name<'a>(state: &'a State, speaker: &Speaker) -> &'a str {
+    let (name, id) = match speaker {
+        Speaker::Sprite(idx) => {
+            let sprite = &state.game.sprites[*idx];
+            (&sprite.name, &sprite.id)
+        }
+        Speaker::Item(idx) => {
+            let item = &state.game.items[*idx];
+            (&item.name, &item.id)
+        }
+    };
+    name.as_deref().unwrap_or(id)
+}
+
+/// Split the text into lines of at most `line_len` characters at spaces.
+fn wrap(text: &str, line_len: usize, lines: &mut Vec<String>) {
+    let mut line = String::new();
+    let mut n_chars = 0;
+    for word in text.split_ascii_whitespace() {
+        let word_len = word.chars().count();
+        if n_chars != 0 && n_chars + 1 + word_len > line_len {
+            lines.push(core::mem::take(&mut line));
+            n_chars = 0;
+        }
+        if n_chars != 0 {
+            line.push(' ');
+            n_chars += 1;
+        }
+        line.push_str(word);
+        n_chars += word_len;
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+    use alloc::vec;
+
+    #[test]
+    fn entries_are_separated_by_empty_lines() {
+        let mut history = History::default();
+        history.push_lines(vec!["a".to_string(), "b".to_string()]);
+        history.push_lines(Vec::new());
+        history.push_lines(vec!["c".to_string()]);
+        assert_eq!(history.lines(), ["a", "b", "", "c"]);
+    }
+
+    #[test]
+    fn oldest_entries_are_forgotten() {
+        let mut history = History::default();
+        for i in 0..=MAX_ENTRIES {
+            history.push_lines(vec![i.to_string(), "-".to_string()]);
+        }
+        let lines = history.lines();
+        assert_eq!(lines.len(), MAX_ENTRIES * 3 - 1);
+        assert_eq!(lines[..3], ["1", "-", ""]);
+        assert_eq!(lines.last().unwrap(), "-");
+    }
+
+    #[test]
+    fn wrap_at_spaces() {
+        let mut lines = Vec::new();
+        wrap("one two three four", 9, &mut lines);
+        assert_eq!(lines, ["one two", "three", "four"]);
+    }
+}
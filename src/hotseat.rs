@@ -0,0 +1,48 @@
+use crate::*;
+use alloc::vec::Vec;
+use firefly_rust as ff;
+
+/// The maximum length of the peer name shown in the turn indicator.
+const MAX_NAME_LEN: usize = 9;
+
+/// Let the first peer take the turn if hot-seat multiplayer is enabled.
+pub fn init_turns(state: &mut State) {
+    if state.config.multiplayer != Multiplayer::Hotseat {
+        return;
+    }
+    let peers = ff::get_peers();
+    if peers.len() < 2 {
+        return;
+    }
+    let peers: Vec<ff::Peer> = peers.iter().collect();
+    state.peer = peers[0];
+    state.turn_peers = peers;
+    state.turn = 0;
+}
+
+/// Pass the turn to the next peer if it's time to do so.
+pub fn pass_turn(state: &mut State, when: Turns) {
+    if state.turn_peers.is_empty() || state.config.turns != when {
+        return;
+    }
+    state.turn = (state.turn + 1) % state.turn_peers.len();
+    state.peer = state.turn_peers[state.turn];
+    state.room_dirty = true;
+}
+
+/// Show the name of the peer whose turn it is on the left of the room.
+pub fn draw_turn(state: &State) {
+    if state.turn_peers.is_empty() {
+        return;
+    }
+    let font = state.font.as_font();
+    // The tile color of the current palette, contrast to the background.
+    let color = ff::Color::new(2);
+    ff::draw_text("TURN:", &font, ff::Point::new(2, 10), color);
+    let name = ff::get_name_buf(state.peer);
+    let name = match name.char_indices().nth(MAX_NAME_LEN) {
+        Some((i, _)) => &name[..i],
+        None => &name,
+    };
+    ff::draw_text(name, &font, ff::Point::new(2, 20), color);
+}
@@ -0,0 +1,496 @@
+use crate::*;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bitsy_file as bf;
+
+/// A dense integer handle of a game object.
+///
+/// The handle is the index of the object in the corresponding list of [`bf::Game`].
+pub type Handle = u16;
+
+/// The handle of the empty cell in the room grid.
+const NO_TILE: Handle = Handle::MAX;
+/// The handle of a missing item or room.
+const NO_HANDLE: Handle = Handle::MAX;
+
+/// Mapping of string IDs of game objects to handles.
+///
+/// Built when the game starts and rebuilt when more segments are parsed.
+/// All references between objects (room tiles, exits, dialogs) are resolved
+/// at the same time, so that the game loop never looks up objects by ID.
+/// Objects are never removed from the game, so handles stay valid.
+#[derive(Default)]
+pub struct Index {
+    palettes: Ids,
+    rooms: Ids,
+    tiles: Ids,
+    sprites: Ids,
+    items: Ids,
+    dialogs: Ids,
+    endings: Ids,
+    /// The resolved references of each room.
+    room_handles: Vec<RoomHandles>,
+    /// The room handle for each sprite, except the avatar.
+    sprite_rooms: Vec<Handle>,
+    /// The dialog handle for each sprite.
+    sprite_dialogs: Vec<Handle>,
+    /// The dialog handle for each item.
+    item_dialogs: Vec<Handle>,
+    /// The handle of the sprite tracking the avatar position.
+    avatar: Handle,
+}
+
+/// The objects referenced by a room, resolved into handles.
+#[derive(Default)]
+struct RoomHandles {
+    /// The tile handles, row by row.
+    grid: Vec<Handle>,
+    /// A bit for each cell (row by row) set if the tile in the cell is a wall.
+    walls: Vec<u64>,
+    /// The item handles, in the same order as the items of the room.
+    items: Vec<Handle>,
+    /// The sprites placed in the room, except the avatar, in ascending order.
+    sprites: Vec<Handle>,
+    /// The target room handle for each exit of the room.
+    exits: Vec<Handle>,
+    /// The dialog handle for each exit of the room.
+    exit_dialogs: Vec<Handle>,
+    /// The ending handle for each ending of the room.
+    endings: Vec<Handle>,
+}
+
+impl Index {
+    pub fn new(game: &bf::Game) -> Self {
+        let mut index = Self::default();
+        index.update(game);
+        index
+    }
+
+    /// Index all the objects added to the game since the last update.
+    ///
+    /// References to objects that were missing are resolved again because
+    /// objects parsed earlier may refer to the new ones.
+    pub fn update(&mut self, game: &bf::Game) {
+        self.palettes.extend(&game.palettes, |p| &p.id);
+        let new_rooms = self.rooms.extend(&game.rooms, |r| &r.id);
+        let new_sprites = self.sprites.extend(&game.sprites, |s| &s.id);
+        let new_items = self.items.extend(&game.items, |i| &i.id);
+        let new_dialogs = self.dialogs.extend(&game.dialogues, |d| &d.id);
+        let new_endings = self.endings.extend(&game.endings, |e| &e.id);
+        // New tiles and items may appear in already indexed rooms.
+        let new_tiles = self.tiles.extend(&game.tiles, |t| &t.id);
+        if new_tiles || new_items {
+            self.room_handles.clear();
+            self.sprite_rooms.clear();
+        }
+        if new_sprites {
+            self.avatar = to_handle(self.sprite("A"));
+        }
+        let old_rooms = self.room_handles.len();
+        if new_rooms || new_dialogs || new_endings {
+            let mut room_handles = core::mem::take(&mut self.room_handles);
+            for (room, handles) in game.rooms.iter().zip(&mut room_handles) {
+                self.resolve_missing(room, handles);
+            }
+            self.room_handles = room_handles;
+        }
+        for room in &game.rooms[old_rooms..] {
+            let handles = self.room_handles(game, room);
+            self.room_handles.push(handles);
+        }
+
+        // Sprites may be placed in rooms that are not parsed yet.
+        if new_rooms || self.sprite_rooms.len() != game.sprites.len() {
+            self.sprite_rooms.resize(game.sprites.len(), NO_HANDLE);
+            for (idx, sprite) in game.sprites.iter().enumerate() {
+                if self.sprite_rooms[idx] != NO_HANDLE {
+                    continue;
+                }
+                let room = self.sprite_room(sprite);
+                self.sprite_rooms[idx] = room;
+                if let Some(handles) = self.room_handles.get_mut(usize::from(room)) {
+                    handles.sprites.push(idx as Handle);
+                }
+            }
+            for handles in &mut self.room_handles {
+                handles.sprites.sort_unstable();
+            }
+        }
+
+        // Dialogs may be parsed after the sprites and items referring to them.
+        if new_dialogs || self.sprite_dialogs.len() != game.sprites.len() {
+            self.sprite_dialogs.resize(game.sprites.len(), NO_HANDLE);
+            for (idx, sprite) in game.sprites.iter().enumerate() {
+                if self.sprite_dialogs[idx] == NO_HANDLE {
+                    let id = sprite.dialogue_id.as_ref().unwrap_or(&sprite.id);
+                    self.sprite_dialogs[idx] = self.dialog_handle(id);
+                }
+            }
+        }
+        if new_dialogs || self.item_dialogs.len() != game.items.len() {
+            self.item_dialogs.resize(game.items.len(), NO_HANDLE);
+            for (idx, item) in game.items.iter().enumerate() {
+                if self.item_dialogs[idx] == NO_HANDLE {
+                    let id = item.dialogue_id.as_ref().unwrap_or(&item.id);
+                    self.item_dialogs[idx] = self.dialog_handle(id);
+                }
+            }
+        }
+    }
+
+    /// Resolve the exits and endings of the room that point to objects not parsed before.
+    fn resolve_missing(&self, room: &bf::Room, handles: &mut RoomHandles) {
+        let exits = room.exits.iter().zip(&mut handles.exits);
+        for (exit, handle) in exits.filter(|(_, h)| **h == NO_HANDLE) {
+            *handle = to_handle(self.room(&exit.exit.room_id));
+        }
+        let dialogs = room.exits.iter().zip(&mut handles.exit_dialogs);
+        for (exit, handle) in dialogs.filter(|(_, h)| **h == NO_HANDLE) {
+            if let Some(id) = &exit.dialogue_id {
+                *handle = self.dialog_handle(id);
+            }
+        }
+        let endings = room.endings.iter().zip(&mut handles.endings);
+        for (ending, handle) in endings.filter(|(_, h)| **h == NO_HANDLE) {
+            *handle = to_handle(self.ending(&ending.id));
+        }
+    }
+
+    fn room_handles(&self, game: &bf::Game, room: &bf::Room) -> RoomHandles {
+        let grid: Vec<Handle> = room.tiles.iter().map(|id| self.tile_handle(id)).collect();
+        let room_walls = room.walls.as_deref().unwrap_or_default();
+        let mut walls = alloc::vec![0; grid.len().div_ceil(64)];
+        for (cell, handle) in grid.iter().enumerate() {
+            let Some(tile) = game.tiles.get(usize::from(*handle)) else {
+                continue;
+            };
+            if tile.wall == Some(true) || room_walls.contains(&tile.id) {
+                walls[cell / 64] |= 1 << (cell % 64);
+            }
+        }
+        let exits = &room.exits;
+        RoomHandles {
+            grid,
+            walls,
+            items: room.items.iter().map(|i| self.item_handle(&i.id)).collect(),
+            sprites: Vec::new(),
+            exits: exits
+                .iter()
+                .map(|e| to_handle(self.room(&e.exit.room_id)))
+                .collect(),
+            exit_dialogs: exits
+                .iter()
+                .map(|e| {
+                    e.dialogue_id
+                        .as_ref()
+                        .map_or(NO_HANDLE, |id| self.dialog_handle(id))
+                })
+                .collect(),
+            endings: room
+                .endings
+                .iter()
+                .map(|e| to_handle(self.ending(&e.id)))
+                .collect(),
+        }
+    }
+
+    pub fn palette(&self, id: &str) -> Option<usize> {
+        self.palettes.get(id)
+    }
+
+    pub fn room(&self, id: &str) -> Option<usize> {
+        self.rooms.get(id)
+    }
+
+    pub fn tile(&self, id: &str) -> Option<usize> {
+        self.tiles.get(id)
+    }
+
+    pub fn sprite(&self, id: &str) -> Option<usize> {
+        self.sprites.get(id)
+    }
+
+    pub fn item(&self, id: &str) -> Option<usize> {
+        self.items.get(id)
+    }
+
+    pub fn dialog(&self, id: &str) -> Option<usize> {
+        self.dialogs.get(id)
+    }
+
+    pub fn ending(&self, id: &str) -> Option<usize> {
+        self.endings.get(id)
+    }
+
+    /// Get the sprite tracking the avatar position.
+    pub fn avatar(&self) -> Option<usize> {
+        from_handle(self.avatar)
+    }
+
+    /// Iterate over all non-empty cells of the room and their tiles.
+    pub fn tiles_in(&self, room: usize) -> impl Iterator<Item = (usize, usize)> + '_ {
+        let grid = self.room_handles.get(room).map(|r| r.grid.as_slice());
+        grid.unwrap_or_default()
+            .iter()
+            .enumerate()
+            .filter(|(_, handle)| **handle != NO_TILE)
+            .map(|(cell, handle)| (cell, usize::from(*handle)))
+    }
+
+    /// Check if the tile in the given cell of the room is a wall.
+    ///
+    /// A tile is a wall if it's marked as a wall or listed as a wall by the room.
+    pub fn is_wall_at(&self, room: usize, cell: usize) -> bool {
+        let Some(handles) = self.room_handles.get(room) else {
+            return false;
+        };
+        match handles.walls.get(cell / 64) {
+            Some(bits) => bits & (1 << (cell % 64)) != 0,
+            None => false,
+        }
+    }
+
+    /// Get the item placed in the room, by its position in the items of the room.
+    pub fn item_in(&self, room: usize, i: usize) -> Option<usize> {
+        from_handle(*self.room_handles.get(room)?.items.get(i)?)
+    }
+
+    /// Track the item added at the end of the items of the room.
+    pub fn push_item(&mut self, room: usize, id: &str) {
+        let handle = self.item_handle(id);
+        if let Some(handles) = self.room_handles.get_mut(room) {
+            handles.items.push(handle);
+        }
+    }
+
+    /// Track the item removed from the items of the room.
+    pub fn remove_item(&mut self, room: usize, i: usize) {
+        if let Some(handles) = self.room_handles.get_mut(room)
+            && i < handles.items.len()
+        {
+            handles.items.remove(i);
+        }
+    }
+
+    /// Get the room the exit leads to, by the position of the exit in the exits of the room.
+    pub fn exit_room(&self, room: usize, i: usize) -> Option<usize> {
+        from_handle(*self.room_handles.get(room)?.exits.get(i)?)
+    }
+
+    /// Get the dialog shown when using the exit, if any.
+    pub fn exit_dialog(&self, room: usize, i: usize) -> Option<usize> {
+        from_handle(*self.room_handles.get(room)?.exit_dialogs.get(i)?)
+    }
+
+    /// Get the ending by its position in the endings of the room.
+    pub fn ending_in(&self, room: usize, i: usize) -> Option<usize> {
+        from_handle(*self.room_handles.get(room)?.endings.get(i)?)
+    }
+
+    /// Get the dialog shown when talking to the sprite.
+    pub fn sprite_dialog(&self, sprite: usize) -> Option<usize> {
+        from_handle(*self.sprite_dialogs.get(sprite)?)
+    }
+
+    /// Get the dialog shown when picking up the item.
+    pub fn item_dialog(&self, item: usize) -> Option<usize> {
+        from_handle(*self.item_dialogs.get(item)?)
+    }
+
+    /// Iterate over all sprites placed in the room, except the avatar.
+    pub fn sprites_in(&self, room: usize) -> impl Iterator<Item = usize> + '_ {
+        let sprites = self.room_handles.get(room).map(|r| r.sprites.as_slice());
+        sprites.unwrap_or_default().iter().map(|s| usize::from(*s))
+    }
+
+    /// Track the room change of the sprite.
+    ///
+    /// The avatar is not tracked, its room is the current room.
+    pub fn place_sprite(&mut self, sprite: usize, room: Option<usize>) {
+        let Some(old_room) = self.sprite_rooms.get_mut(sprite) else {
+            return;
+        };
+        let handle = sprite as Handle;
+        if let Some(handles) = self.room_handles.get_mut(usize::from(*old_room))
+            && let Ok(i) = handles.sprites.binary_search(&handle)
+        {
+            handles.sprites.remove(i);
+        }
+        *old_room = to_handle(room);
+        if let Some(handles) = self.room_handles.get_mut(usize::from(*old_room))
+            && let Err(i) = handles.sprites.binary_search(&handle)
+        {
+            handles.sprites.insert(i, handle);
+        }
+    }
+
+    fn tile_handle(&self, id: &str) -> Handle {
+        // "0" is the empty cell.
+        if id == "0" {
+            return NO_TILE;
+        }
+        to_handle(self.tile(id))
+    }
+
+    fn item_handle(&self, id: &str) -> Handle {
+        to_handle(self.item(id))
+    }
+
+    fn dialog_handle(&self, id: &str) -> Handle {
+        to_handle(self.dialog(id))
+    }
+
+    fn sprite_room(&self, sprite: &bf::Sprite) -> Handle {
+        if sprite.id == "A" {
+            return NO_HANDLE;
+        }
+        to_handle(sprite.room_id.as_deref().and_then(|id| self.room(id)))
+    }
+}
+
+fn to_handle(idx: Option<usize>) -> Handle {
+    match idx {
+        Some(idx) => idx as Handle,
+        None => NO_HANDLE,
+    }
+}
+
+fn from_handle(handle: Handle) -> Option<usize> {
+    if handle == NO_HANDLE {
+        return None;
+    }
+    Some(usize::from(handle))
+}
+
+impl HeapSize for Index {
+    fn heap_size(&self) -> usize {
+        let mut size = self.palettes.heap_size() + self.rooms.heap_size();
+        size += self.tiles.heap_size() + self.sprites.heap_size();
+        size += self.items.heap_size() + self.dialogs.heap_size();
+        size += self.endings.heap_size() + self.room_handles.heap_size();
+        size += self.sprite_rooms.heap_size() + self.sprite_dialogs.heap_size();
+        size + self.item_dialogs.heap_size()
+    }
+}
+
+impl HeapSize for RoomHandles {
+    fn heap_size(&self) -> usize {
+        let mut size = self.grid.heap_size() + self.walls.heap_size();
+        size += self.items.heap_size() + self.sprites.heap_size();
+        size += self.exits.heap_size() + self.exit_dialogs.heap_size();
+        size + self.endings.heap_size()
+    }
+}
+
+/// Handles of objects of one kind.
+#[derive(Default)]
+struct Ids {
+    handles: BTreeMap<String, Handle>,
+    /// How many objects are indexed, including the ones with duplicate IDs.
+    len: usize,
+}
+
+impl Ids {
+    /// Index the objects after the already indexed ones.
+    ///
+    /// Returns true if any new objects were indexed.
+    fn extend<T>(&mut self, objects: &[T], id: fn(&T) -> &String) -> bool {
+        let old_len = self.len;
+        for object in &objects[old_len..] {
+            // Like the lookups in bitsy-file, the first object with the ID wins.
+            let handle = self.len as Handle;
+            self.handles.entry(id(object).clone()).or_insert(handle);
+            self.len += 1;
+        }
+        self.len != old_len
+    }
+
+    fn get(&self, id: &str) -> Option<usize> {
+        let handle = self.handles.get(id)?;
+        Some(usize::from(*handle))
+    }
+}
+
+impl HeapSize for Ids {
+    fn heap_size(&self) -> usize {
+        self.handles.heap_size()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::format;
+    use alloc::vec;
+
+    fn room(id: &str, first_row: &str, extra: &str) -> String {
+        let empty = ["0"; 16].join(",");
+        let rows = vec![empty.as_str(); 15].join("\n");
+        format!("ROOM {id}\n{first_row}\n{rows}\n{extra}PAL 0\n")
+    }
+
+    fn game() -> bf::Game {
+        let first_row = format!("a,b,0{}", ",0".repeat(13));
+        let start = room("0", &first_row, "WAL b\nEXT 5,0 1 0,0\nEND 0 6,0\n");
+        let text = format!(
+            "Test\n\n# BITSY VERSION 8.12\n\n! ROOM_FORMAT 1\n\n\
+             PAL 0\n0,0,0\n255,255,255\n255,255,255\n\n{start}\n\
+             TIL a\n{tile}WAL true\n\nTIL b\n{tile}\n\
+             SPR A\n{tile}POS 0 4,4\n\n\
+             SPR cat\n{tile}DLG hello\nPOS 1 8,8\n\n\
+             SPR dog\n{tile}POS 0 5,5\n",
+            tile = "00000000\n".repeat(8),
+        );
+        bf::Game::from(&text).unwrap()
+    }
+
+    /// Parse the room, the dialog, and the ending the game refers to but doesn't have yet.
+    fn load_lazy(game: &mut bf::Game) {
+        let room = room("1", &["0"; 16].join(","), "");
+        let text = format!("{NOOP_SEGMENT}\n\n{room}\nDLG hello\nHi!\n\nEND 0\nBye!\n");
+        for segment in bf::Segments::new(&text) {
+            game.push_segment(segment);
+        }
+    }
+
+    #[test]
+    fn references_are_resolved_when_lazy_segments_are_parsed() {
+        let mut game = game();
+        let mut index = Index::new(&game);
+        assert_eq!(index.avatar(), Some(0));
+        assert_eq!(index.exit_room(0, 0), None);
+        assert_eq!(index.ending_in(0, 0), None);
+        assert_eq!(index.sprite_dialog(1), None);
+        assert_eq!(index.sprites_in(0).collect::<Vec<_>>(), [2]);
+
+        load_lazy(&mut game);
+        index.update(&game);
+        assert_eq!(index.exit_room(0, 0), Some(1));
+        assert_eq!(index.exit_dialog(0, 0), None);
+        assert_eq!(index.ending_in(0, 0), Some(0));
+        assert_eq!(index.sprite_dialog(1), Some(0));
+        assert_eq!(index.sprites_in(1).collect::<Vec<_>>(), [1]);
+    }
+
+    #[test]
+    fn walls_include_tiles_listed_by_the_room() {
+        let index = Index::new(&game());
+        assert!(index.is_wall_at(0, 0));
+        assert!(index.is_wall_at(0, 1));
+        assert!(!index.is_wall_at(0, 2));
+        assert!(!index.is_wall_at(1, 0));
+    }
+
+    #[test]
+    fn placed_sprites_are_kept_in_order() {
+        let mut game = game();
+        load_lazy(&mut game);
+        let mut index = Index::new(&game);
+        index.place_sprite(2, Some(1));
+        assert_eq!(index.sprites_in(0).count(), 0);
+        assert_eq!(index.sprites_in(1).collect::<Vec<_>>(), [1, 2]);
+        index.place_sprite(1, None);
+        assert_eq!(index.sprites_in(1).collect::<Vec<_>>(), [2]);
+    }
+}
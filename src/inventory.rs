@@ -0,0 +1,67 @@
+use crate::*;
+use alloc::format;
+use alloc::vec::Vec;
+use firefly_rust as ff;
+
+/// The index of the inventory item in the app menu.
+pub const MENU_INVENTORY: u8 = 1;
+/// The vertical space occupied by each item in the list.
+const ROW_HEIGHT: i32 = 12;
+/// Where the items list starts.
+const LIST_Y: i32 = 18;
+/// How many items fit on the screen.
+const PAGE_SIZE: usize = 11;
+
+/// Scroll the inventory with up and down, close it with anything else.
+pub fn handle_inventory_pad(state: &mut State, pressed: ff::DPad4) {
+    let Overlay::Inventory(offset) = state.overlay else {
+        return;
+    };
+    let n_items = collected_items(state).len();
+    let offset = match pressed {
+        ff::DPad4::None => return,
+        ff::DPad4::Up => offset.saturating_sub(1),
+        ff::DPad4::Down if offset + PAGE_SIZE < n_items => offset + 1,
+        ff::DPad4::Down => offset,
+        _ => {
+            state.close_overlay();
+            return;
+        }
+    };
+    state.overlay = Overlay::Inventory(offset);
+}
+
+pub fn draw_inventory(state: &State, offset: usize) {
+    let items = collected_items(state);
+    let font = state.font.as_font();
+    let char_width = i32::from(font.char_width());
+    let mut y = LIST_Y;
+    for (idx, count) in draw_list_overlay(state, "INVENTORY", &items, offset, PAGE_SIZE) {
+        let item = &state.game.items[*idx];
+        let frame = state.item_frames(*idx).pick(state.render_frame);
+        let primary = state.colors.get(item.colour_id, 2);
+        draw_bitsy_image(frame, primary, ff::Point::new(8, y));
+
+        let name = item.name.as_ref().unwrap_or(&item.id);
+        let point = ff::Point::new(20, y + 7);
+        ff::draw_text(name, &font, point, COLOR_DIALOG_TEXT);
+
+        let count = format!("x{count}");
+        let x = ff::WIDTH - 8 - char_width * count.len() as i32;
+        ff::draw_text(&count, &font, ff::Point::new(x, y + 7), COLOR_DIALOG_TEXT);
+        y += ROW_HEIGHT;
+    }
+}
+
+/// Get the handles of all items the player has and their counts,
+/// in the order they are defined in the game.
+pub fn collected_items(state: &State) -> Vec<(usize, u16)> {
+    let mut items = Vec::new();
+    for (idx, item) in state.game.items.iter().enumerate() {
+        let count = state.script_state.inventory.get(&item.id);
+        if count != 0 {
+            items.push((idx, count));
+        }
+    }
+    items
+}
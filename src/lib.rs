@@ -0,0 +1,70 @@
+//! Interpreter for running Bitsy games on Firefly Zero.
+//!
+//! The standalone interpreter (`main.rs`) runs the game from the ROM files,
+//! but other Firefly games can embed a Bitsy game as one of their scenes using [`Engine`].
+#![no_std]
+extern crate alloc;
+
+mod badges;
+mod binary;
+mod blocks;
+mod boards;
+mod choices;
+mod commands;
+mod config;
+mod debug;
+mod dev_menu;
+mod dialog;
+mod engine;
+mod functions;
+mod history;
+mod hotseat;
+mod index;
+mod inventory;
+mod loading;
+mod memory;
+mod multiplayer;
+mod palette;
+#[cfg(any(test, feature = "writer"))]
+mod precompile;
+mod rendering;
+mod rng;
+mod state;
+mod tags;
+mod updating;
+
+use crate::badges::*;
+use crate::binary::*;
+use crate::blocks::*;
+use crate::boards::*;
+use crate::choices::*;
+use crate::commands::*;
+use crate::debug::*;
+use crate::dev_menu::*;
+use crate::dialog::*;
+use crate::functions::*;
+use crate::history::*;
+use crate::hotseat::*;
+use crate::index::*;
+use crate::inventory::*;
+use crate::loading::*;
+use crate::memory::*;
+use crate::multiplayer::*;
+use crate::palette::*;
+use crate::rendering::*;
+use crate::rng::*;
+use crate::state::*;
+use crate::tags::*;
+use crate::updating::*;
+
+pub use crate::config::{
+    BadgeRule, BoardRule, Config, DialogBox, Multiplayer, Score, Submit, Trigger, Turns,
+};
+pub use crate::engine::{Engine, Event, Input, LoadError};
+pub use crate::functions::{ApplyFn, EvalFn, Function, Param};
+#[cfg(feature = "writer")]
+pub use crate::precompile::{TooBig, write_binary};
+#[cfg(feature = "writer")]
+pub use crate::state::parse_room_avatars;
+pub use bitsy_script;
+pub use bitsy_script::Val;
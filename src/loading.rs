@@ -0,0 +1,134 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+const TRIPLE_QUOTE: &str = r#"""""#;
+
+/// The first words of lines that start a new segment in a game file.
+const SEGMENT_KEYWORDS: &[&str] = &[
+    "PAL",
+    "ROOM",
+    "SET",
+    "TIL",
+    "SPR",
+    "ITM",
+    "DLG",
+    "END",
+    "VAR",
+    "FONT",
+    "DEFAULT_FONT",
+    "TEXT_DIRECTION",
+];
+
+/// A segment that doesn't affect the parsed game.
+///
+/// The parser treats the first segment as the game title unless it looks like
+/// a header. Starting the lazy part of the game with this segment makes sure
+/// that the first lazy segment isn't parsed as the title.
+pub const NOOP_SEGMENT: &str = "TEXT_DIRECTION LTR";
+
+/// Split the game file into the part that must be parsed before the game starts
+/// and the part that can be parsed later, while the game is running.
+///
+/// The eager part includes everything except rooms (other than the starting one),
+/// dialogs, and endings. If the starting room cannot be found,
+/// the whole game is eager.
+pub fn split_game(raw: &str) -> (String, String) {
+    let raw = raw.replace("\r\n", "\n");
+    let blocks = split_blocks(&raw);
+    let Some(start_room) = find_start_room(&raw, &blocks) else {
+        return (raw, String::from(NOOP_SEGMENT));
+    };
+    let mut eager: Vec<&str> = Vec::new();
+    let mut lazy: Vec<&str> = Vec::from([NOOP_SEGMENT]);
+    for (i, block) in blocks.into_iter().enumerate() {
+        let block = &raw[block];
+        // The first block is the game title (or the header).
+        if i != 0 && is_lazy(block, start_room) {
+            lazy.push(block);
+        } else {
+            eager.push(block);
+        }
+    }
+    (eager.join("\n\n"), lazy.join("\n\n"))
+}
+
+/// Split the game file into blocks, each block starting with a new segment.
+///
+/// Blocks are separated by empty lines. Empty lines inside of
+/// triple-quoted text and empty lines not followed by a new segment
+/// (like in old-style dialogs) don't start a new block.
+fn split_blocks(raw: &str) -> Vec<Range<usize>> {
+    let mut blocks = Vec::new();
+    let mut start: Option<usize> = None;
+    let mut quoted = false;
+    let mut offset = 0;
+    for line in raw.split_inclusive('\n') {
+        let line_start = offset;
+        offset += line.len();
+        let line = line.trim_end_matches('\n');
+        if line == TRIPLE_QUOTE {
+            quoted = !quoted;
+        }
+        if line.is_empty() && !quoted {
+            if let Some(start) = start.take() {
+                push_block(&mut blocks, raw, start..line_start);
+            }
+            continue;
+        }
+        if start.is_none() {
+            start = Some(line_start);
+        }
+    }
+    if let Some(start) = start {
+        push_block(&mut blocks, raw, start..raw.len());
+    }
+    blocks
+}
+
+fn push_block(blocks: &mut Vec<Range<usize>>, raw: &str, block: Range<usize>) {
+    let text = raw[block.clone()].trim_end_matches('\n');
+    let end = block.start + text.len();
+    if let Some(last) = blocks.last_mut()
+        && !starts_segment(text)
+    {
+        last.end = end;
+        return;
+    }
+    blocks.push(block.start..end);
+}
+
+fn starts_segment(block: &str) -> bool {
+    if block.starts_with("# BITSY VERSION") || block.starts_with('!') {
+        return true;
+    }
+    let first_word = block.split_ascii_whitespace().next().unwrap_or_default();
+    SEGMENT_KEYWORDS.contains(&first_word)
+}
+
+/// Find the ID of the room where the avatar starts.
+fn find_start_room<'a>(raw: &'a str, blocks: &[Range<usize>]) -> Option<&'a str> {
+    for block in blocks {
+        let mut lines = raw[block.clone()].lines();
+        if lines.next() != Some("SPR A") {
+            continue;
+        }
+        for line in lines {
+            if let Some(pos) = line.strip_prefix("POS ") {
+                return pos.split_ascii_whitespace().next();
+            }
+        }
+        return None;
+    }
+    None
+}
+
+fn is_lazy(block: &str, start_room: &str) -> bool {
+    let first_line = block.lines().next().unwrap_or_default();
+    let (first_word, id) = first_line.split_once(' ').unwrap_or_default();
+    match first_word {
+        "ROOM" | "SET" => id.trim_ascii() != start_room,
+        "DLG" | "END" => true,
+        _ => false,
+    }
+}
@@ -1,30 +1,35 @@
 #![no_std]
 #![no_main]
-extern crate alloc;
 
-mod dialog;
-mod rendering;
-mod state;
-mod updating;
+use core::cell::OnceCell;
+use firefly_bitsy::{Engine, Input};
 
-use crate::dialog::*;
-use crate::rendering::*;
-use crate::state::*;
-use crate::updating::*;
+static mut ENGINE: OnceCell<Engine> = OnceCell::new();
+
+fn get_engine() -> &'static mut Engine {
+    #[allow(static_mut_refs)]
+    unsafe { ENGINE.get_mut() }.unwrap()
+}
 
 #[unsafe(no_mangle)]
 extern "C" fn boot() {
-    load_state();
+    let engine = Engine::from_rom();
+    engine.add_menu_items();
+    #[allow(static_mut_refs)]
+    unsafe { ENGINE.set(engine) }.ok().unwrap();
 }
 
 #[unsafe(no_mangle)]
 extern "C" fn update() {
-    let state = get_state();
-    update_state(state);
+    get_engine().update(Input::Device);
 }
 
 #[unsafe(no_mangle)]
 extern "C" fn render() {
-    let state = get_state();
-    render_room(state);
+    get_engine().render();
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn handle_menu(index: u32) {
+    get_engine().handle_menu(index as u8);
 }
@@ -0,0 +1,307 @@
+use crate::*;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use bitsy_file as bf;
+use core::mem::size_of;
+use firefly_rust as ff;
+
+/// How often (in updates) to log the memory usage if enabled in the config.
+const REPORT_INTERVAL: u16 = 60 * 10;
+
+/// The approximate number of bytes an object owns on the heap.
+///
+/// Allocator overhead and unused space in hash maps are not counted,
+/// so the real usage is always a bit higher.
+pub trait HeapSize {
+    fn heap_size(&self) -> usize;
+}
+
+/// Log the memory usage if enabled in the config and it's time to do so.
+pub fn report_memory_periodically(state: &State) {
+    if state.update_frame.is_multiple_of(REPORT_INTERVAL) {
+        report_memory(state);
+    }
+}
+
+/// Log the memory usage of each subsystem if enabled in the config.
+pub fn report_memory(state: &State) {
+    if !state.config.log_memory {
+        return;
+    }
+    let precompiled = state.scripts.heap_size() + state.frames.heap_size();
+    log_size("game", state.game.heap_size() + precompiled);
+    log_size("index", state.index.heap_size());
+    log_size("images", state.tiles.heap_size());
+    log_size("dialog", dialog_heap_size(state));
+    log_size("history", state.history.heap_size());
+    log_size("script", script_heap_size(state));
+    if let Some(segments) = &state.lazy_segments {
+        ff::log_debug(&format!("memory: {} segments not parsed", segments.len()));
+    }
+    log_size("total", total_memory());
+}
+
+fn log_size(name: &str, size: usize) {
+    ff::log_debug(&format!("memory: {name} {} KB", size.div_ceil(1024)));
+}
+
+/// The size of the whole linear memory, including the stack and the free heap.
+fn total_memory() -> usize {
+    #[cfg(target_family = "wasm")]
+    {
+        const PAGE_SIZE: usize = 64 * 1024;
+        core::arch::wasm32::memory_size(0) * PAGE_SIZE
+    }
+    #[cfg(not(target_family = "wasm"))]
+    0
+}
+
+fn dialog_heap_size(state: &State) -> usize {
+    let mut size = state.dialog.heap_size();
+    for player in &state.players {
+        size += player.dialog.heap_size();
+    }
+    size
+}
+
+/// The size of variables and inventory of [`bitsy_script::State`] and block counters.
+///
+/// The script state doesn't allow to iterate over them,
+/// so only the ones declared in the game are counted.
+fn script_heap_size(state: &State) -> usize {
+    let script = &state.script_state;
+    let mut size = script.room.heap_size() + script.avatar.heap_size();
+    size += script.palette.heap_size();
+    for var in &state.game.variables {
+        size += var.id.len() + size_of::<(String, bitsy_script::Val)>();
+        if let bitsy_script::Val::S(val) = script.vars.get(&var.id) {
+            size += val.heap_size();
+        }
+    }
+    for item in &state.game.items {
+        if script.inventory.get(&item.id) != 0 {
+            size += item.id.len() + size_of::<(String, u16)>();
+        }
+    }
+    size + state.counters.heap_size()
+}
+
+impl HeapSize for String {
+    fn heap_size(&self) -> usize {
+        self.capacity()
+    }
+}
+
+impl HeapSize for u8 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for u16 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl HeapSize for u64 {
+    fn heap_size(&self) -> usize {
+        0
+    }
+}
+
+impl<T: HeapSize> HeapSize for Vec<T> {
+    fn heap_size(&self) -> usize {
+        let items: usize = self.iter().map(HeapSize::heap_size).sum();
+        self.capacity() * size_of::<T>() + items
+    }
+}
+
+impl<T: HeapSize> HeapSize for Option<T> {
+    fn heap_size(&self) -> usize {
+        match self {
+            Some(val) => val.heap_size(),
+            None => 0,
+        }
+    }
+}
+
+impl<A: HeapSize, B: HeapSize> HeapSize for (A, B) {
+    fn heap_size(&self) -> usize {
+        self.0.heap_size() + self.1.heap_size()
+    }
+}
+
+impl<K: HeapSize, V: HeapSize> HeapSize for BTreeMap<K, V> {
+    fn heap_size(&self) -> usize {
+        let items: usize = self
+            .iter()
+            .map(|(k, v)| k.heap_size() + v.heap_size())
+            .sum();
+        self.len() * size_of::<(K, V)>() + items
+    }
+}
+
+impl HeapSize for bf::Game {
+    fn heap_size(&self) -> usize {
+        let mut size = self.name.heap_size();
+        size += self.custom_font.heap_size() + self.font_data.heap_size();
+        size += self.palettes.heap_size() + self.rooms.heap_size();
+        size += self.tiles.heap_size() + self.sprites.heap_size();
+        size += self.items.heap_size() + self.dialogues.heap_size();
+        size += self.endings.heap_size() + self.variables.heap_size();
+        size += self.warnings.capacity() * size_of::<bf::Error>();
+        size
+    }
+}
+
+impl HeapSize for bf::Palette {
+    fn heap_size(&self) -> usize {
+        let colours = self.colours.capacity() * size_of::<bf::Colour>();
+        self.id.heap_size() + self.name.heap_size() + colours
+    }
+}
+
+impl HeapSize for bf::Room {
+    fn heap_size(&self) -> usize {
+        let mut size = self.id.heap_size() + self.palette_id.heap_size();
+        size += self.name.heap_size() + self.tiles.heap_size();
+        size += self.items.heap_size() + self.endings.heap_size();
+        size += self.exits.heap_size() + self.walls.heap_size();
+        size
+    }
+}
+
+impl HeapSize for bf::Instance {
+    fn heap_size(&self) -> usize {
+        self.id.heap_size()
+    }
+}
+
+impl HeapSize for bf::ExitInstance {
+    fn heap_size(&self) -> usize {
+        self.exit.room_id.heap_size() + self.dialogue_id.heap_size()
+    }
+}
+
+impl HeapSize for bf::Image {
+    fn heap_size(&self) -> usize {
+        self.pixels.heap_size()
+    }
+}
+
+impl HeapSize for bf::Tile {
+    fn heap_size(&self) -> usize {
+        self.id.heap_size() + self.name.heap_size() + self.animation_frames.heap_size()
+    }
+}
+
+impl HeapSize for bf::Sprite {
+    fn heap_size(&self) -> usize {
+        let mut size = self.id.heap_size() + self.name.heap_size();
+        size += self.animation_frames.heap_size() + self.items.heap_size();
+        size += self.dialogue_id.heap_size() + self.room_id.heap_size();
+        size
+    }
+}
+
+impl HeapSize for bf::Item {
+    fn heap_size(&self) -> usize {
+        let mut size = self.id.heap_size() + self.name.heap_size();
+        size += self.animation_frames.heap_size() + self.dialogue_id.heap_size();
+        size
+    }
+}
+
+impl HeapSize for bf::Dialogue {
+    fn heap_size(&self) -> usize {
+        self.id.heap_size() + self.contents.heap_size() + self.name.heap_size()
+    }
+}
+
+impl HeapSize for bf::Ending {
+    fn heap_size(&self) -> usize {
+        self.id.heap_size() + self.dialogue.heap_size()
+    }
+}
+
+impl HeapSize for bf::Variable {
+    fn heap_size(&self) -> usize {
+        self.id.heap_size() + self.initial_value.heap_size()
+    }
+}
+
+impl HeapSize for PackedFrames {
+    fn heap_size(&self) -> usize {
+        self.tiles.heap_size() + self.sprites.heap_size() + self.items.heap_size()
+    }
+}
+
+impl HeapSize for PackedImage {
+    fn heap_size(&self) -> usize {
+        self.as_bytes().len()
+    }
+}
+
+impl HeapSize for Scripts {
+    fn heap_size(&self) -> usize {
+        self.dialogs.heap_size() + self.endings.heap_size()
+    }
+}
+
+impl HeapSize for bitsy_script::Token {
+    /// Only words are counted, tags are rare and small.
+    fn heap_size(&self) -> usize {
+        match self {
+            bitsy_script::Token::Word(word) => word.heap_size(),
+            _ => 0,
+        }
+    }
+}
+
+impl HeapSize for Dialog {
+    fn heap_size(&self) -> usize {
+        let commands = self.commands.capacity() * size_of::<Command>();
+        self.pages.heap_size() + commands
+    }
+}
+
+impl HeapSize for History {
+    fn heap_size(&self) -> usize {
+        let lines = self.lines().iter();
+        lines
+            .map(|line| size_of::<String>() + line.heap_size())
+            .sum()
+    }
+}
+
+impl HeapSize for Page {
+    fn heap_size(&self) -> usize {
+        self.words.heap_size() + self.choice.heap_size()
+    }
+}
+
+impl HeapSize for Choice {
+    fn heap_size(&self) -> usize {
+        self.options.heap_size() + self.rest.heap_size()
+    }
+}
+
+impl HeapSize for ChoiceOption {
+    fn heap_size(&self) -> usize {
+        self.label.heap_size() + self.tokens.heap_size()
+    }
+}
+
+impl HeapSize for Word {
+    fn heap_size(&self) -> usize {
+        use bitsy_script::Word::*;
+        match &self.word {
+            Text(text, _) => text.heap_size(),
+            Sprite(id) | Tile(id) | Item(id) => id.heap_size(),
+            LineBreak | PageBreak => 0,
+        }
+    }
+}
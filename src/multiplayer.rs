@@ -0,0 +1,124 @@
+use crate::*;
+use alloc::string::String;
+use alloc::vec::Vec;
+use firefly_rust as ff;
+
+/// The state of a player in a multiplayer game.
+///
+/// Only one player is active at a time. The state of the active player
+/// lives in [`State`] (so that all the single-player code works as is)
+/// and is moved back into [`Player`] when another player is activated.
+/// The shared parts of the state (the world, variables, inventory)
+/// are not moved, so they stay the same for all players.
+pub struct Player {
+    /// The device controlling the player. Never moved into [`State`].
+    pub peer: ff::Peer,
+    pub room: usize,
+    pub room_id: String,
+    pub pos_x: u8,
+    pub pos_y: u8,
+    pub avatar: String,
+    pub room_avatar: Option<String>,
+    pub avatar_sprite: Option<usize>,
+    pub palette: String,
+    pub applied_palette: String,
+    pub dpad: ff::DPad4,
+    pub held_for: u32,
+    /// The player's own dialog, if dialogs are not shared.
+    pub dialog: Dialog,
+}
+
+/// Create a player for each connected peer if multiplayer is enabled.
+///
+/// All players start at the same place as the first one.
+pub fn init_players(state: &mut State) {
+    if state.config.multiplayer != Multiplayer::Coop {
+        return;
+    }
+    let peers = ff::get_peers();
+    if peers.len() < 2 {
+        return;
+    }
+    let title = state.game.name.clone();
+    let mut players = Vec::new();
+    for peer in peers {
+        let dialog = if state.config.shared_dialogs {
+            Dialog::default()
+        } else {
+            new_dialog(state, &title, true)
+        };
+        players.push(Player {
+            peer,
+            room: state.room,
+            room_id: state.script_state.room.clone(),
+            pos_x: state.script_state.pos_x,
+            pos_y: state.script_state.pos_y,
+            avatar: state.script_state.avatar.clone(),
+            room_avatar: state.room_avatar.clone(),
+            avatar_sprite: state.avatar_sprite,
+            palette: state.script_state.palette.clone(),
+            applied_palette: state.palette.clone(),
+            dpad: ff::DPad4::default(),
+            held_for: 0,
+            dialog,
+        });
+    }
+    state.peer = players[0].peer;
+    state.players = players;
+    state.player = 0;
+}
+
+/// Make the player with the given index active.
+pub fn switch_player(state: &mut State, idx: usize) {
+    if idx == state.player || idx >= state.players.len() {
+        return;
+    }
+    let mut players = core::mem::take(&mut state.players);
+    exchange(state, &mut players[state.player]);
+    exchange(state, &mut players[idx]);
+    state.peer = players[idx].peer;
+    state.players = players;
+    state.player = idx;
+}
+
+/// Make the player controlled by the current device active, so that it's rendered.
+///
+/// Called at the end of each update, after all the game logic.
+/// The next update doesn't depend on which player is active
+/// because it activates every player in order.
+pub fn activate_local_player(state: &mut State) {
+    if state.players.is_empty() {
+        return;
+    }
+    switch_player(state, local_player(state));
+    state.sync_tiles();
+}
+
+/// Get the index of the player controlled by the current device.
+///
+/// **IMPORTANT:** use it only to choose which player to show. Updating the state
+/// based on the local device would cause state drift between devices.
+fn local_player(state: &State) -> usize {
+    let me = ff::get_me();
+    let idx = state.players.iter().position(|player| player.peer == me);
+    idx.unwrap_or_default()
+}
+
+/// Swap the state of the active player with the given player.
+fn exchange(state: &mut State, player: &mut Player) {
+    use core::mem::swap;
+    swap(&mut state.room, &mut player.room);
+    swap(&mut state.script_state.room, &mut player.room_id);
+    swap(&mut state.script_state.pos_x, &mut player.pos_x);
+    swap(&mut state.script_state.pos_y, &mut player.pos_y);
+    swap(&mut state.script_state.avatar, &mut player.avatar);
+    swap(&mut state.room_avatar, &mut player.room_avatar);
+    swap(&mut state.avatar_sprite, &mut player.avatar_sprite);
+    swap(&mut state.script_state.palette, &mut player.palette);
+    swap(&mut state.palette, &mut player.applied_palette);
+    swap(&mut state.dpad, &mut player.dpad);
+    swap(&mut state.held_for, &mut player.held_for);
+    if !state.config.shared_dialogs {
+        swap(&mut state.dialog, &mut player.dialog);
+    }
+}
@@ -0,0 +1,101 @@
+use alloc::vec::Vec;
+use firefly_rust as ff;
+
+/// The number of Firefly colors available for Bitsy palette colors.
+///
+/// The last 3 Firefly colors are reserved for the UI:
+/// rainbow text, dialog box, and dialog text.
+pub const GAME_COLORS: usize = 13;
+
+/// Maps colors of a Bitsy palette to the Firefly colors.
+///
+/// Bitsy palettes can have any number of colors while Firefly has only 16.
+/// The first [`GAME_COLORS`] Bitsy colors get a Firefly color each,
+/// and each of the remaining colors is mapped to the closest allocated color.
+#[derive(Default)]
+pub struct ColorMap {
+    /// The RGB values for the allocated Firefly colors, starting from the first one.
+    rgbs: Vec<ff::RGB>,
+    /// For each Bitsy color, the index of the allocated Firefly color.
+    ///
+    /// The index is 0-based, the same as pixel values in Firefly images.
+    indices: Vec<u8>,
+}
+
+impl ColorMap {
+    pub fn new(palette: &bitsy_file::Palette) -> Self {
+        let mut rgbs = Vec::new();
+        let mut indices = Vec::new();
+        for color in &palette.colours {
+            let rgb = convert_color(color);
+            if rgbs.len() < GAME_COLORS {
+                indices.push(rgbs.len() as u8);
+                rgbs.push(rgb);
+            } else {
+                indices.push(find_closest(&rgbs, rgb));
+            }
+        }
+        Self { rgbs, indices }
+    }
+
+    /// Get the image pixel value for the given Bitsy color index.
+    ///
+    /// If the current palette doesn't have the color, the default color is used.
+    pub fn get(&self, color_id: Option<u64>, default: u8) -> u8 {
+        if let Some(color_id) = color_id
+            && let Some(idx) = self.indices.get(color_id as usize)
+        {
+            return *idx;
+        }
+        self.indices
+            .get(usize::from(default))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Get the Firefly color and its RGB value for the given Bitsy color index.
+    ///
+    /// Returns `None` if the current palette doesn't have the color.
+    pub fn color(&self, color_id: usize) -> Option<(ff::Color, ff::RGB)> {
+        let idx = *self.indices.get(color_id)?;
+        let rgb = *self.rgbs.get(usize::from(idx))?;
+        Some((ff::Color::new(idx + 1), rgb))
+    }
+
+    /// Set the RGB values of the allocated Firefly colors.
+    pub fn apply(&self) {
+        for (rgb, idx) in self.rgbs.iter().zip(1_u8..) {
+            ff::set_color(ff::Color::new(idx), *rgb);
+        }
+    }
+}
+
+pub fn convert_color(c: &bitsy_file::Colour) -> ff::RGB {
+    ff::RGB {
+        r: c.red,
+        g: c.green,
+        b: c.blue,
+    }
+}
+
+/// Find the index of the color closest to the given one.
+fn find_closest(rgbs: &[ff::RGB], rgb: ff::RGB) -> u8 {
+    let mut best_idx = 0;
+    let mut best_dist = u32::MAX;
+    for (other, idx) in rgbs.iter().zip(0u8..) {
+        let dist = distance(*other, rgb);
+        if dist < best_dist {
+            best_idx = idx;
+            best_dist = dist;
+        }
+    }
+    best_idx
+}
+
+/// Squared Euclidean distance between two colors.
+fn distance(c1: ff::RGB, c2: ff::RGB) -> u32 {
+    let r = u32::from(c1.r.abs_diff(c2.r));
+    let g = u32::from(c1.g.abs_diff(c2.g));
+    let b = u32::from(c1.b.abs_diff(c2.b));
+    r * r + g * g + b * b
+}
@@ -0,0 +1,701 @@
+use crate::*;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use bitsy_file as bf;
+use bitsy_script as bs;
+use core::fmt;
+
+/// A value that doesn't fit into the precompiled game format,
+/// like a room with more than 255 items.
+#[derive(Debug, PartialEq, Eq)]
+pub struct TooBig {
+    pub what: &'static str,
+    pub value: usize,
+}
+
+impl fmt::Display for TooBig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "too many {}: {}", self.what, self.value)
+    }
+}
+
+/// Convert a parsed Bitsy game into the precompiled format read by [`load_binary`].
+///
+/// Used by `tools/bitsy-bin`, so that the writer and the reader
+/// live next to each other and share the dialog tokenization.
+pub fn write_binary(game: &bf::Game, room_avatars: &[(String, String)]) -> Result<Vec<u8>, TooBig> {
+    let mut w = Writer::default();
+    w.str(&game.name)?;
+    w.u16("palettes", game.palettes.len())?;
+    for palette in &game.palettes {
+        w.palette(palette)?;
+    }
+    w.u16("tiles", game.tiles.len())?;
+    for tile in &game.tiles {
+        w.tile(tile)?;
+    }
+    w.u16("sprites", game.sprites.len())?;
+    for sprite in &game.sprites {
+        w.sprite(sprite)?;
+    }
+    w.u16("items", game.items.len())?;
+    for item in &game.items {
+        w.item(item)?;
+    }
+    w.u16("rooms", game.rooms.len())?;
+    for room in &game.rooms {
+        let avatar = room_avatars.iter().find(|(id, _)| id == &room.id);
+        w.room(room, avatar.map(|(_, avatar)| avatar.as_str()))?;
+    }
+    w.u16("dialogs", game.dialogues.len())?;
+    for dialog in &game.dialogues {
+        w.id(&dialog.id)?;
+        w.opt_id(dialog.name.as_deref())?;
+        w.script(&dialog.contents)?;
+    }
+    w.u16("endings", game.endings.len())?;
+    for ending in &game.endings {
+        w.id(&ending.id)?;
+        w.script(&ending.dialogue)?;
+    }
+    w.u16("variables", game.variables.len())?;
+    for var in &game.variables {
+        w.id(&var.id)?;
+        w.str(&var.initial_value)?;
+    }
+    w.finish()
+}
+
+#[derive(Default)]
+struct Writer {
+    body: Vec<u8>,
+    strings: Vec<String>,
+    indices: BTreeMap<String, u16>,
+}
+
+impl Writer {
+    /// Produce the file: the header, the string table, and the body.
+    fn finish(self) -> Result<Vec<u8>, TooBig> {
+        let mut header = Writer::default();
+        header.body.extend_from_slice(MAGIC);
+        header.byte(VERSION);
+        header.u16("strings", self.strings.len())?;
+        for s in &self.strings {
+            header.str(s)?;
+        }
+        let mut data = header.body;
+        data.extend_from_slice(&self.body);
+        Ok(data)
+    }
+
+    fn byte(&mut self, val: u8) {
+        self.body.push(val);
+    }
+
+    /// Write the number of the given things as a single byte.
+    fn u8(&mut self, what: &'static str, val: usize) -> Result<(), TooBig> {
+        let Ok(val) = u8::try_from(val) else {
+            return Err(TooBig { what, value: val });
+        };
+        self.byte(val);
+        Ok(())
+    }
+
+    /// Write the number of the given things as two bytes.
+    fn u16(&mut self, what: &'static str, val: usize) -> Result<(), TooBig> {
+        let Ok(val) = u16::try_from(val) else {
+            return Err(TooBig { what, value: val });
+        };
+        self.body.extend_from_slice(&val.to_le_bytes());
+        Ok(())
+    }
+
+    fn str(&mut self, s: &str) -> Result<(), TooBig> {
+        self.u16("bytes in a string", s.len())?;
+        self.body.extend_from_slice(s.as_bytes());
+        Ok(())
+    }
+
+    /// Write the index of the string in the string table.
+    fn id(&mut self, s: &str) -> Result<(), TooBig> {
+        let idx = match self.indices.get(s) {
+            Some(idx) => *idx,
+            None => {
+                let n_strings = self.strings.len();
+                // The last index marks a missing value.
+                let idx = match u16::try_from(n_strings) {
+                    Ok(idx) if idx != NONE => idx,
+                    _ => {
+                        return Err(TooBig {
+                            what: "strings",
+                            value: n_strings + 1,
+                        });
+                    }
+                };
+                self.strings.push(s.to_string());
+                self.indices.insert(s.to_string(), idx);
+                idx
+            }
+        };
+        self.body.extend_from_slice(&idx.to_le_bytes());
+        Ok(())
+    }
+
+    fn opt_id(&mut self, s: Option<&str>) -> Result<(), TooBig> {
+        match s {
+            Some(s) => self.id(s),
+            None => {
+                self.body.extend_from_slice(&NONE.to_le_bytes());
+                Ok(())
+            }
+        }
+    }
+
+    /// Write the colour index, the last byte value marks a missing colour.
+    fn colour(&mut self, colour: Option<u64>) -> Result<(), TooBig> {
+        let Some(colour) = colour else {
+            self.byte(NO_COLOUR);
+            return Ok(());
+        };
+        match u8::try_from(colour) {
+            Ok(colour) if colour != NO_COLOUR => {
+                self.byte(colour);
+                Ok(())
+            }
+            _ => Err(TooBig {
+                what: "colours",
+                value: usize::try_from(colour).unwrap_or(usize::MAX),
+            }),
+        }
+    }
+
+    fn pos(&mut self, pos: bf::Position) {
+        self.byte(pos.x);
+        self.byte(pos.y);
+    }
+
+    fn frames(&mut self, frames: &[bf::Image]) -> Result<(), TooBig> {
+        self.u8("animation frames", frames.len())?;
+        for frame in frames {
+            self.image(frame);
+        }
+        Ok(())
+    }
+
+    /// Write the image in the Firefly format with the set bits having the pixel value 1.
+    fn image(&mut self, image: &bf::Image) {
+        let mut buf = [0; MAX_IMAGE_SIZE];
+        self.body
+            .extend_from_slice(write_image(Frame::Bitsy(image), 1, &mut buf));
+    }
+
+    fn palette(&mut self, palette: &bf::Palette) -> Result<(), TooBig> {
+        self.id(&palette.id)?;
+        self.opt_id(palette.name.as_deref())?;
+        self.u8("colours in a palette", palette.colours.len())?;
+        for colour in &palette.colours {
+            self.body
+                .extend_from_slice(&[colour.red, colour.green, colour.blue]);
+        }
+        Ok(())
+    }
+
+    fn tile(&mut self, tile: &bf::Tile) -> Result<(), TooBig> {
+        self.id(&tile.id)?;
+        self.opt_id(tile.name.as_deref())?;
+        self.byte(match tile.wall {
+            None => 0,
+            Some(false) => 1,
+            Some(true) => 2,
+        });
+        self.colour(tile.colour_id)?;
+        self.frames(&tile.animation_frames)
+    }
+
+    fn sprite(&mut self, sprite: &bf::Sprite) -> Result<(), TooBig> {
+        self.id(&sprite.id)?;
+        self.opt_id(sprite.name.as_deref())?;
+        self.opt_id(sprite.dialogue_id.as_deref())?;
+        self.opt_id(sprite.room_id.as_deref())?;
+        match sprite.position {
+            Some(pos) => {
+                self.byte(1);
+                self.pos(pos);
+            }
+            None => self.byte(0),
+        }
+        self.colour(sprite.colour_id)?;
+        self.u8("items of a sprite", sprite.items.len())?;
+        for item in &sprite.items {
+            self.id(item)?;
+        }
+        self.frames(&sprite.animation_frames)
+    }
+
+    fn item(&mut self, item: &bf::Item) -> Result<(), TooBig> {
+        self.id(&item.id)?;
+        self.opt_id(item.name.as_deref())?;
+        self.opt_id(item.dialogue_id.as_deref())?;
+        self.colour(item.colour_id)?;
+        self.frames(&item.animation_frames)
+    }
+
+    fn room(&mut self, room: &bf::Room, avatar: Option<&str>) -> Result<(), TooBig> {
+        self.id(&room.id)?;
+        self.opt_id(room.palette_id.as_deref())?;
+        self.opt_id(room.name.as_deref())?;
+        self.opt_id(avatar)?;
+        self.u16("tiles in a room", room.tiles.len())?;
+        for tile in &room.tiles {
+            self.id(tile)?;
+        }
+        self.u8("items in a room", room.items.len())?;
+        for item in &room.items {
+            self.id(&item.id)?;
+            self.pos(item.position);
+        }
+        self.u8("exits in a room", room.exits.len())?;
+        for exit in &room.exits {
+            self.pos(exit.position);
+            self.id(&exit.exit.room_id)?;
+            self.pos(exit.exit.position);
+            self.opt_id(exit.dialogue_id.as_deref())?;
+        }
+        self.u8("endings in a room", room.endings.len())?;
+        for ending in &room.endings {
+            self.id(&ending.id)?;
+            self.pos(ending.position);
+        }
+        match &room.walls {
+            Some(walls) => {
+                self.byte(1);
+                self.u8("walls in a room", walls.len())?;
+                for wall in walls {
+                    self.id(wall)?;
+                }
+            }
+            None => self.byte(0),
+        }
+        Ok(())
+    }
+
+    /// Tokenize the dialog script the same way as [`Dialog::new`] does.
+    fn script(&mut self, script: &str) -> Result<(), TooBig> {
+        let script = strip_triple_quotes(script);
+        // Empty dialogs are not shown at all.
+        let tokens: Vec<bs::Token> = if script.trim().is_empty() {
+            Vec::new()
+        } else {
+            bs::Tokenizer::new(&fold_tags(script)).collect()
+        };
+        self.u16("tokens in a dialog", tokens.len())?;
+        for token in &tokens {
+            self.token(token)?;
+        }
+        Ok(())
+    }
+
+    fn token(&mut self, token: &bs::Token) -> Result<(), TooBig> {
+        match token {
+            bs::Token::OpenTag(tag) => {
+                self.byte(0);
+                self.tag(tag)
+            }
+            bs::Token::CloseTag(tag) => {
+                self.byte(1);
+                self.tag(tag)
+            }
+            bs::Token::Word(word) => {
+                self.byte(2);
+                self.str(word)
+            }
+        }
+    }
+
+    fn tag(&mut self, tag: &bs::Tag) -> Result<(), TooBig> {
+        use bs::Tag::*;
+        match tag {
+            Br => self.byte(0),
+            Pg => self.byte(1),
+            Eff(effect) => {
+                self.byte(2);
+                self.effect(*effect);
+            }
+            End => self.byte(3),
+            Say(expr) => {
+                self.byte(4);
+                self.expr(expr)?;
+            }
+            DrwT(id) => {
+                self.byte(5);
+                self.str(id)?;
+            }
+            DrwS(id) => {
+                self.byte(6);
+                self.str(id)?;
+            }
+            DrwI(id) => {
+                self.byte(7);
+                self.str(id)?;
+            }
+            Pal(id) => {
+                self.byte(8);
+                self.str(id)?;
+            }
+            Ava(id) => {
+                self.byte(9);
+                self.str(id)?;
+            }
+            Exit(id, x, y) => {
+                self.byte(10);
+                self.str(id)?;
+                self.byte(*x);
+                self.byte(*y);
+            }
+            Set(name, expr) => {
+                self.byte(11);
+                self.str(name)?;
+                self.expr(expr)?;
+            }
+            Unknown(name, args) => {
+                self.byte(12);
+                self.str(name)?;
+                self.str(args)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn effect(&mut self, effect: bs::TextEffect) {
+        use bs::TextEffect::*;
+        match effect {
+            None => self.byte(0),
+            Wavy => self.byte(1),
+            Shaky => self.byte(2),
+            Rainbow => self.byte(3),
+            Color(c) => {
+                self.byte(4);
+                self.byte(c);
+            }
+        }
+    }
+
+    fn expr(&mut self, expr: &bs::Expr) -> Result<(), TooBig> {
+        match expr {
+            bs::Expr::SimpleExpr(expr) => {
+                self.byte(0);
+                self.simple_expr(expr)
+            }
+            bs::Expr::BinOp(op, left, right) => {
+                self.byte(1);
+                self.bin_op(*op);
+                self.simple_expr(left)?;
+                self.simple_expr(right)
+            }
+        }
+    }
+
+    fn bin_op(&mut self, op: bs::BinOp) {
+        use bs::BinOp::*;
+        self.byte(match op {
+            Mul => 0,
+            Div => 1,
+            Add => 2,
+            Sub => 3,
+            Lt => 4,
+            Gt => 5,
+            Lte => 6,
+            Gte => 7,
+            Eq => 8,
+        });
+    }
+
+    fn simple_expr(&mut self, expr: &bs::SimpleExpr) -> Result<(), TooBig> {
+        match expr {
+            bs::SimpleExpr::Var(name) => {
+                self.byte(0);
+                self.str(name)
+            }
+            bs::SimpleExpr::Item(name) => {
+                self.byte(1);
+                self.str(name)
+            }
+            bs::SimpleExpr::Val(val) => {
+                self.byte(2);
+                self.val(val)
+            }
+        }
+    }
+
+    fn val(&mut self, val: &bs::Val) -> Result<(), TooBig> {
+        match val {
+            bs::Val::Undef => self.byte(0),
+            bs::Val::I(i) => {
+                self.byte(1);
+                self.body.extend_from_slice(&i.to_le_bytes());
+            }
+            bs::Val::S(s) => {
+                self.byte(2);
+                self.str(s)?;
+            }
+            bs::Val::F(f) => {
+                self.byte(3);
+                self.body.extend_from_slice(&f.to_le_bytes());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec;
+
+    const GAME: &str = r#"A tiny game
+
+# BITSY VERSION 8.12
+
+! VER_MAJ 8
+! VER_MIN 12
+! ROOM_FORMAT 1
+
+PAL 0
+NAME night
+0,82,204
+128,159,255
+255,255,255
+
+ROOM 0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,a,a,a,a,a,a,a,a,a,a,a,a,a,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,0,0,0,0,0,0,0,0,0,0,0,0,a,0
+0,a,a,a,a,a,a,a,a,a,a,a,a,a,a,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+NAME garden
+ITM 0 3,4
+EXT 13,13 1 2,2
+END 0 12,2
+PAL 0
+AVA cat
+
+ROOM 1
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+0,0,0,0,0,0,0,0,0,0,0,0,0,0,0,0
+NAME house
+PAL 0
+
+TIL a
+11111111
+10000001
+10000001
+10011001
+10011001
+10000001
+10000001
+11111111
+>
+11111111
+10000001
+10000001
+10000001
+10000001
+10000001
+10000001
+11111111
+NAME wall
+WAL true
+
+SPR A
+00011000
+00011000
+00011000
+00111100
+01111110
+10111101
+00100100
+00100100
+POS 0 4,4
+
+SPR cat
+00000000
+00000000
+01010001
+01110001
+01110010
+01111100
+00111100
+00100100
+DLG 0
+POS 0 8,8
+COL 2
+
+ITM 0
+00000000
+00000000
+00000000
+00111100
+01100100
+00100100
+00011000
+00000000
+NAME tea
+DLG 1
+
+DLG 0
+"""
+{sequence
+  - Meow?
+  - {wvy}Meow!{wvy}
+}
+"""
+NAME cat dialog
+
+DLG 1
+You found a cup of tea.{tea = tea + 1}
+
+END 0
+The end.
+
+VAR tea
+42
+"#;
+
+    #[test]
+    fn round_trip() {
+        let game = bf::Game::from(GAME).unwrap();
+        let room_avatars = parse_room_avatars(GAME);
+        assert_eq!(room_avatars, [("0".into(), "cat".into())]);
+        let data = write_binary(&game, &room_avatars).unwrap();
+        assert!(is_binary(&data));
+        let binary = load_binary(&data).unwrap();
+        let loaded = &binary.game;
+
+        assert_eq!(loaded.name, game.name);
+        assert_eq!(loaded.palettes, game.palettes);
+        assert_eq!(loaded.rooms, game.rooms);
+        assert_eq!(loaded.variables, game.variables);
+        assert_eq!(binary.room_avatars, room_avatars);
+
+        assert_eq!(loaded.tiles.len(), game.tiles.len());
+        let frames = &binary.frames;
+        for ((loaded, tile), packed) in loaded.tiles.iter().zip(&game.tiles).zip(&frames.tiles) {
+            assert_eq!(loaded.id, tile.id);
+            assert_eq!(loaded.name, tile.name);
+            assert_eq!(loaded.wall, tile.wall);
+            assert_eq!(loaded.colour_id, tile.colour_id);
+            assert_same_frames(packed, &tile.animation_frames);
+        }
+        assert_eq!(loaded.sprites.len(), game.sprites.len());
+        let sprites = loaded.sprites.iter().zip(&game.sprites);
+        for ((loaded, sprite), packed) in sprites.zip(&frames.sprites) {
+            assert_eq!(loaded.id, sprite.id);
+            assert_eq!(loaded.dialogue_id, sprite.dialogue_id);
+            assert_eq!(loaded.room_id, sprite.room_id);
+            assert_eq!(loaded.position, sprite.position);
+            assert_eq!(loaded.colour_id, sprite.colour_id);
+            assert_same_frames(packed, &sprite.animation_frames);
+        }
+        assert_eq!(loaded.items.len(), game.items.len());
+        for ((loaded, item), packed) in loaded.items.iter().zip(&game.items).zip(&frames.items) {
+            assert_eq!(loaded.id, item.id);
+            assert_eq!(loaded.name, item.name);
+            assert_eq!(loaded.dialogue_id, item.dialogue_id);
+            assert_same_frames(packed, &item.animation_frames);
+        }
+
+        // Dialogs are tokenized the same way as when the game is loaded from text.
+        assert_eq!(loaded.dialogues.len(), game.dialogues.len());
+        for (tokens, dialog) in binary.scripts.dialogs.iter().zip(&game.dialogues) {
+            let text = fold_tags(strip_triple_quotes(&dialog.contents));
+            let expected: Vec<bs::Token> = bs::Tokenizer::new(&text).collect();
+            assert_eq!(tokens, &expected);
+        }
+        let sequence = binary.scripts.dialogs[0]
+            .iter()
+            .find_map(|token| match token {
+                bs::Token::OpenTag(bs::Tag::Unknown(name, args)) => Some((name, args)),
+                _ => None,
+            });
+        let (name, args) = sequence.unwrap();
+        assert_eq!(name.trim_end_matches(LINE_SEP), "sequence");
+        assert_eq!(
+            split_options(&unfold_tag(name, args)),
+            ["Meow?", "{wvy}Meow!{wvy}"]
+        );
+        assert_eq!(binary.scripts.endings.len(), game.endings.len());
+    }
+
+    fn assert_same_frames(packed: &[PackedImage], frames: &[bf::Image]) {
+        assert_eq!(packed.len(), frames.len());
+        for (packed, frame) in packed.iter().zip(frames) {
+            let size = frame.pixels.len();
+            let pixels: Vec<u8> = (0..size).map(|i| u8::from(packed.is_set(i))).collect();
+            assert_eq!(pixels, frame.pixels);
+            assert_eq!(Frame::Packed(packed).width(), Frame::Bitsy(frame).width());
+        }
+    }
+
+    #[test]
+    fn too_many_items_in_room() {
+        let mut game = bf::Game::from(GAME).unwrap();
+        let item = game.rooms[0].items[0].clone();
+        game.rooms[0].items = vec![item; 256];
+        let err = write_binary(&game, &[]).unwrap_err();
+        let expected = TooBig {
+            what: "items in a room",
+            value: 256,
+        };
+        assert_eq!(err, expected);
+    }
+
+    #[test]
+    fn load_errors() {
+        let game = bf::Game::from(GAME).unwrap();
+        let mut data = write_binary(&game, &[]).unwrap();
+        data.truncate(data.len() - 1);
+        assert_eq!(load_binary(&data).err(), Some(LoadError::InvalidBinary));
+        data[MAGIC.len()] = VERSION + 1;
+        assert_eq!(load_binary(&data).err(), Some(LoadError::WrongVersion));
+    }
+
+    #[test]
+    fn colour_out_of_range() {
+        let mut game = bf::Game::from(GAME).unwrap();
+        game.sprites[1].colour_id = Some(254);
+        assert!(write_binary(&game, &[]).is_ok());
+        game.sprites[1].colour_id = Some(255);
+        let err = write_binary(&game, &[]).unwrap_err();
+        let expected = TooBig {
+            what: "colours",
+            value: 255,
+        };
+        assert_eq!(err, expected);
+    }
+}
@@ -1,5 +1,4 @@
 use crate::*;
-use alloc::vec;
 use alloc::vec::Vec;
 use firefly_rust as ff;
 
@@ -9,13 +8,25 @@ const OFFSET_X: i32 = (ff::WIDTH - 8 * 16) / 2;
 const OFFSET_Y: i32 = 0;
 /// Controls how fast, relative to the `update` speed, the room and word animations play.
 const ANIMATION_DELAY: u16 = 25;
-/// Control how fast, relative to the `update` speed, the new dialog words are printed.
-const DIALOG_DELAY: u16 = 3;
-
-const COLOR_BG: ff::Color = ff::Color::new(1);
-const COLOR_RAINBOW: ff::Color = ff::Color::LightGreen;
-const COLOR_DIALOG_BOX: ff::Color = ff::Color::Gray;
-const COLOR_DIALOG_TEXT: ff::Color = ff::Color::DarkGray;
+/// The minimum contrast ratio of colored dialog text against the dialog box.
+///
+/// Lower than for the dialog box itself because colored text is usually
+/// short and used for emphasis. 3 is the WCAG minimum for large text.
+const MIN_EFFECT_CONTRAST: f32 = 3.0;
+/// The width of the part of the dialog box occupied by the speaker portrait.
+const PORTRAIT_WIDTH: i32 = 32;
+/// The first byte of a Firefly image.
+pub const IMAGE_MAGIC: u8 = 0x22;
+/// The size of the Firefly image header: the magic number, the width, and the transparency.
+pub const IMAGE_HEADER_SIZE: usize = 4;
+/// The size of the biggest Firefly image converted from a Bitsy image (16x16).
+pub const MAX_IMAGE_SIZE: usize = IMAGE_HEADER_SIZE + 16 * 16 / 2;
+
+pub const COLOR_BG: ff::Color = ff::Color::new(1);
+// The last 3 colors are reserved for the UI, see [`GAME_COLORS`].
+const COLOR_RAINBOW: ff::Color = ff::Color::LightGray;
+pub const COLOR_DIALOG_BOX: ff::Color = ff::Color::Gray;
+pub const COLOR_DIALOG_TEXT: ff::Color = ff::Color::DarkGray;
 
 const RAINBOW_COLORS: &[ff::RGB] = &[
     ff::RGB::new(255, 0, 0),   // red
@@ -35,16 +46,23 @@ pub fn render_room(state: &mut State) {
     let new_frame = state.render_frame != render_frame;
     state.render_frame = render_frame;
 
-    if !state.segments.is_empty() {
+    if state.is_loading() {
         draw_progress_bar(state);
         return;
     }
 
+    if state.overlay != Overlay::None {
+        draw_overlay(state);
+        return;
+    }
+
     if state.script_state.end && state.dialog.n_pages() == 0 {
         draw_end(state);
         return;
     }
-    let render_room = !state.script_state.end && (new_frame | state.room_dirty);
+    // Centered dialogs (the title and endings) are shown instead of the room.
+    let centered = state.dialog.center && state.dialog.n_pages() != 0;
+    let render_room = !state.script_state.end && !centered && (new_frame | state.room_dirty);
     if render_room {
         state.room_dirty = false;
         clear_room(state);
@@ -52,7 +70,14 @@ pub fn render_room(state: &mut State) {
         draw_tiles(state);
         draw_items(state);
         draw_sprites(state);
+        draw_players(state);
         draw_avatar(state);
+        draw_debug(state);
+        draw_turn(state);
+        // The room was drawn over the dialog box.
+        if state.config.dialog_box == DialogBox::Overlay {
+            state.dialog.redraw();
+        }
     }
     draw_dialog(state);
 }
@@ -66,8 +91,10 @@ fn draw_progress_bar(state: &State) {
     let point = ff::Point::new(x, y);
     ff::draw_text(TEXT, &font, point, ff::Color::Gray);
 
-    if state.n_segments != 0 {
-        let segments_left = state.n_segments - state.segments.len();
+    if let Some(segments) = &state.segments
+        && state.n_segments != 0
+    {
+        let segments_left = state.n_segments - segments.len();
         let progress = TEXT.len() * segments_left / state.n_segments;
         ff::draw_text(&TEXT[..progress], &font, point, ff::Color::White);
     }
@@ -84,42 +111,52 @@ fn draw_end(state: &State) {
 }
 
 fn set_palette(state: &State) {
-    let room = &state.game.rooms[state.room];
-    let palette = match &room.palette_id {
-        Some(id) => id.as_str(),
-        None => "0",
+    if state.get_palette().is_none() {
+        return;
     };
-    let palette = state.game.get_palette(palette).unwrap();
-    for (color, idx) in palette.colours.iter().zip(1_usize..) {
-        let idx = ff::Color::from(idx as u8);
-        let rgb = convert_color(color);
-        ff::set_color(idx, rgb);
-    }
+    state.colors.apply();
+    let (box_rgb, text_rgb) = dialog_rgbs(state);
+    ff::set_color(COLOR_DIALOG_BOX, box_rgb);
+    ff::set_color(COLOR_DIALOG_TEXT, text_rgb);
+}
 
+/// Get the RGB values of the dialog box and the dialog text for the current palette.
+fn dialog_rgbs(state: &State) -> (ff::RGB, ff::RGB) {
     // If the base palette colors are contrast enough,
     // use them for the dialog box as well.
     // It's usually true but some games can play around with palette.
     // For example, to have "hidden" tiles in a room.
-    if palette.colours.len() >= 2 {
+    if let Some(palette) = state.get_palette()
+        && palette.colours.len() >= 2
+    {
         let bg = convert_color(&palette.colours[0]);
         let fg = convert_color(&palette.colours[1]);
         if is_contrast(bg, fg) {
-            ff::set_color(COLOR_DIALOG_BOX, bg);
-            ff::set_color(COLOR_DIALOG_TEXT, fg);
-            return;
+            return (bg, fg);
         }
     };
-
-    ff::set_color(COLOR_DIALOG_BOX, ff::RGB::new(0x21, 0x1e, 0x20));
-    ff::set_color(COLOR_DIALOG_TEXT, ff::RGB::new(0xe9, 0xef, 0xec));
+    (
+        ff::RGB::new(0x21, 0x1e, 0x20),
+        ff::RGB::new(0xe9, 0xef, 0xec),
+    )
 }
 
-fn convert_color(c: &bitsy_file::Colour) -> ff::RGB {
-    ff::RGB {
-        r: c.red,
-        g: c.green,
-        b: c.blue,
+/// Get the color of the text with the `{clr1}`..`{clr3}` effect.
+///
+/// The effect uses a color of the current palette (`{clr1}` is the first one).
+/// If the color would be hard to read on the dialog box,
+/// the regular dialog text color is used instead.
+fn effect_color(state: &State, color: u8) -> ff::Color {
+    // `{clr}` without the number is the same as `{clr1}`.
+    let color_id = usize::from(color.saturating_sub(1));
+    let Some((color, rgb)) = state.colors.color(color_id) else {
+        return COLOR_DIALOG_TEXT;
+    };
+    let (box_rgb, _) = dialog_rgbs(state);
+    if contrast_ratio(rgb, box_rgb) < MIN_EFFECT_CONTRAST {
+        return COLOR_DIALOG_TEXT;
     }
+    color
 }
 
 fn draw_tiles(state: &State) {
@@ -133,8 +170,24 @@ fn draw_tiles(state: &State) {
     }
 }
 
+fn draw_overlay(state: &mut State) {
+    match state.overlay {
+        Overlay::None => {}
+        Overlay::Inventory(offset) => draw_inventory(state, offset),
+        Overlay::Dev(menu) => draw_dev_menu(state, menu),
+        // The history isn't animated, so it's redrawn only when scrolled.
+        Overlay::History(offset) => {
+            if state.drawn_overlay != Some(state.overlay) {
+                draw_history(state, offset);
+            }
+        }
+    }
+    state.drawn_overlay = Some(state.overlay);
+}
+
 fn clear_room(state: &State) {
-    if state.dialog.n_pages() == 0 {
+    // The dialog box must be preserved unless it will be redrawn anyway.
+    if !state.dialog.is_started() {
         ff::clear_screen(COLOR_BG);
     }
     let point = ff::Point::new(OFFSET_X, OFFSET_Y);
@@ -144,90 +197,143 @@ fn clear_room(state: &State) {
 
 fn draw_items(state: &State) {
     let room = &state.game.rooms[state.room];
-    for item in &room.items {
+    for (i, item) in room.items.iter().enumerate() {
         let pos = &item.position;
-        let id = &item.id;
-        let Some(item) = state.game.items.iter().find(|item| &item.id == id) else {
+        let Some(idx) = state.index.item_in(state.room, i) else {
             continue;
         };
-        let frame = pick_frame(&item.animation_frames, state.render_frame);
-        let primary = match item.colour_id {
-            Some(c) => c as u8,
-            None => 2,
-        };
-        let image = parse_image(frame, primary);
-        let image = unsafe { ff::Image::from_bytes(&image) };
-        let point = tile_point(pos.x, pos.y);
-        ff::draw_image(&image, point);
+        let item = &state.game.items[idx];
+        let frame = state.item_frames(idx).pick(state.render_frame);
+        let primary = state.colors.get(item.colour_id, 2);
+        draw_bitsy_image(frame, primary, tile_point(pos.x, pos.y));
     }
 }
 
 fn draw_sprites(state: &State) {
-    let room = &state.game.rooms[state.room];
-    for sprite in &state.game.sprites {
-        if sprite.id == "A" {
+    for idx in state.index.sprites_in(state.room) {
+        if let Some(pos) = state.game.sprites[idx].position {
+            draw_sprite(state, idx, pos);
+        }
+    }
+}
+
+/// In multiplayer, draw avatars of other players in the same room.
+fn draw_players(state: &State) {
+    for (i, player) in state.players.iter().enumerate() {
+        if i == state.player || player.room != state.room {
             continue;
         }
-        let Some(room_id) = sprite.room_id.as_ref() else {
+        let Some(idx) = player.avatar_sprite else {
             continue;
         };
-        if room_id == &room.id {
-            draw_sprite(sprite, state.render_frame);
-        }
+        let pos = bitsy_file::Position {
+            x: player.pos_x,
+            y: player.pos_y,
+        };
+        draw_sprite(state, idx, pos);
     }
 }
 
+/// Draw the avatar at the player's position.
+///
+/// The sprite used to draw the avatar can be changed by the room or by scripts.
 fn draw_avatar(state: &State) {
-    for sprite in &state.game.sprites {
-        if sprite.id == state.script_state.avatar {
-            draw_sprite(sprite, state.render_frame);
-            return;
+    let Some(idx) = state.avatar_sprite else {
+        return;
+    };
+    draw_sprite(state, idx, state.pos());
+}
+
+fn draw_sprite(state: &State, idx: usize, pos: bitsy_file::Position) {
+    let frame = state.sprite_frames(idx).pick(state.render_frame);
+    let primary = state.colors.get(state.game.sprites[idx].colour_id, 2);
+    draw_bitsy_image(frame, primary, tile_point(pos.x, pos.y));
+}
+
+/// The position and size of the dialog box.
+///
+/// Like in Bitsy, the box over the room moves to the top of the room
+/// when the avatar is in the lower half, so that it never hides the avatar.
+fn dialog_rect(state: &State, center: bool) -> (ff::Point, ff::Size) {
+    const HEIGHT: i32 = 32;
+    let room_height = i32::from(TILES_Y) * 8;
+    if center {
+        return (
+            ff::Point::new(0, room_height / 2),
+            ff::Size::new(ff::WIDTH, HEIGHT),
+        );
+    }
+    match state.config.dialog_box {
+        DialogBox::Below => (
+            ff::Point::new(0, OFFSET_Y + room_height),
+            ff::Size::new(ff::WIDTH, HEIGHT),
+        ),
+        DialogBox::Overlay => {
+            let y = if state.pos().y >= TILES_Y / 2 {
+                OFFSET_Y
+            } else {
+                OFFSET_Y + room_height - HEIGHT
+            };
+            let width = i32::from(TILES_X) * 8;
+            (ff::Point::new(OFFSET_X, y), ff::Size::new(width, HEIGHT))
         }
     }
 }
 
-fn draw_sprite(sprite: &bitsy_file::Sprite, frame: u16) {
-    let frame = pick_frame(&sprite.animation_frames, frame);
-    let Some(pos) = &sprite.position else {
-        return;
-    };
-    let primary = match sprite.colour_id {
-        Some(c) => c as u8,
-        None => 2,
-    };
-    let image = parse_image(frame, primary);
-    let image = unsafe { ff::Image::from_bytes(&image) };
-    let point = tile_point(pos.x, pos.y);
-    ff::draw_image(&image, point);
+/// Check if the dialog box has the portrait of the speaker on the left.
+fn has_portrait(state: &State, center: bool, speaker: Option<&Speaker>) -> bool {
+    state.config.portraits && !center && speaker.is_some()
+}
+
+/// How much text fits into the dialog box.
+///
+/// The text is narrower if the box has the speaker portrait.
+pub fn text_layout(state: &State, center: bool, speaker: Option<&Speaker>) -> TextLayout {
+    let font = state.font.as_font();
+    let (_, size) = dialog_rect(state, center);
+    let mut width = size.width;
+    if has_portrait(state, center, speaker) {
+        width -= PORTRAIT_WIDTH;
+    }
+    TextLayout {
+        char_width: font.char_width(),
+        char_height: font.char_height(),
+        width: width as usize,
+    }
 }
 
 fn draw_dialog(state: &mut State) {
     const MARGIN_X: i32 = 2;
 
-    let dialog_frame = state.update_frame / DIALOG_DELAY;
+    // Zero delay means the pages are shown without the typewriter animation.
+    let delay = state.config.text_delay;
+    let dialog_frame = state.update_frame / delay.max(1);
     let new_frame = state.dialog_frame != dialog_frame;
     state.dialog_frame = dialog_frame;
 
+    let (point, size) = dialog_rect(state, state.dialog.center);
     let center = state.dialog.center;
+    let portrait = has_portrait(state, center, state.dialog.speaker.as_ref());
+    // The colors for `{clr}`, `{clr1}`, `{clr2}`, and `{clr3}`.
+    let effect_colors: [ff::Color; 4] = core::array::from_fn(|c| effect_color(state, c as u8));
     let Some(page) = state.dialog.current_page() else {
         return;
     };
-    // Slow down word rendering.
-    if !page.fast && !new_frame {
+    // Slow down letter rendering.
+    if !page.fast && !page.redraw && !new_frame {
         return;
     }
 
-    let y = if center { 128 / 2 } else { OFFSET_Y + 128 };
-    let point = ff::Point::new(0, y);
     if !page.started {
         page.started = true;
         if center {
             ff::clear_screen(COLOR_BG);
         }
-        let size = ff::Size::new(ff::WIDTH, 32);
         let style = ff::Style::solid(COLOR_DIALOG_BOX);
         ff::draw_rect(point, size, style);
     }
+    let redraw = page.redraw;
+    page.redraw = false;
 
     // Cycle the RGB representation of the color representing the rainbow text.
     let idx = usize::from(state.render_frame) % RAINBOW_COLORS.len();
@@ -235,17 +341,38 @@ fn draw_dialog(state: &mut State) {
     ff::set_color(COLOR_RAINBOW, rainbow_color);
 
     let font = state.font.as_font();
-    let point = ff::Point::new(point.x + MARGIN_X, point.y + 10);
+    let text_x = if portrait { PORTRAIT_WIDTH } else { MARGIN_X };
+    let text_point = ff::Point::new(point.x + text_x, point.y + 10);
+    if let Some(choice) = &page.choice {
+        draw_choice(choice, &font, text_point);
+    }
+    // How many letters can be revealed in this frame.
+    let mut budget = if page.fast || delay == 0 {
+        usize::MAX
+    } else {
+        usize::from(new_frame)
+    };
     for word in &mut page.words {
         use bitsy_script::Word::*;
+        let n_letters = word.n_letters();
+        let reveal = budget.min(n_letters - word.shown.min(n_letters));
+        word.shown += reveal;
+        budget -= reveal;
+        // The word and all words after it are still hidden.
+        if word.shown == 0 && n_letters != 0 {
+            break;
+        }
+        let text = word.shown_text();
         match &word.word {
-            Text(text, effect) => {
+            Text(_, effect) => {
                 use bitsy_script::TextEffect::*;
-                let stable = matches!(effect, None | Color(_));
-                if word.rendered && (stable || !new_frame) {
+                // Rainbow text changes its color through the palette
+                // and so it doesn't need to be redrawn.
+                let stable = matches!(effect, None | Color(_) | Rainbow);
+                if reveal == 0 && !redraw && (stable || !new_frame) {
                     continue;
                 }
-                let mut word_point = point + word.point;
+                let mut word_point = text_point + word.point;
                 let mut color = COLOR_DIALOG_TEXT;
                 let mut wave = false;
 
@@ -268,21 +395,23 @@ fn draw_dialog(state: &mut State) {
                         None => {}
                         Wavy => wave = true,
                         Shaky => {
-                            let rand = randomize(state.render_frame);
-                            let shift_x = rand % 2 - 1;
-                            let shift_y = (rand >> 8) % 2 - 1;
-                            word_point.x += shift_x as i32;
-                            word_point.y += shift_y as i32;
+                            let shift_x = state.effects_rng.below(2) as i32 - 1;
+                            let shift_y = state.effects_rng.below(2) as i32 - 1;
+                            word_point.x += shift_x;
+                            word_point.y += shift_y;
                         }
                         Rainbow => color = COLOR_RAINBOW,
-                        Color(c) => color = ff::Color::new(*c),
+                        Color(c) => {
+                            let c = usize::from(*c);
+                            color = effect_colors.get(c).copied().unwrap_or(COLOR_DIALOG_TEXT);
+                        }
                     }
                 }
 
                 if wave {
                     // Draw the wavy word letter-by-letter.
-                    for i in 0..text.len() {
-                        let sub = &text[i..=i];
+                    for (i, (start, letter)) in text.char_indices().enumerate() {
+                        let sub = &text[start..start + letter.len_utf8()];
                         let shift_x = (i * usize::from(font.char_width())) as i32;
                         let shift_y = ((state.render_frame + i as u16) % 2) as i32;
                         let point = word_point + ff::Point::new(shift_x, shift_y);
@@ -291,12 +420,6 @@ fn draw_dialog(state: &mut State) {
                 } else {
                     ff::draw_text(text, &font, word_point, color);
                 }
-
-                let was_rendered = word.rendered;
-                word.rendered = true;
-                if !was_rendered && !page.fast {
-                    return;
-                }
             }
             Sprite(_) => {}
             Tile(_) => {}
@@ -304,56 +427,247 @@ fn draw_dialog(state: &mut State) {
             LineBreak | PageBreak => {}
         };
     }
-    if state.dialog.n_pages() > 1 {
-        draw_dialog_arrow(state)
+    // Like in Bitsy, the arrow appears only when the page is fully revealed.
+    if page.all_rendered() && state.dialog.n_pages() > 1 {
+        draw_dialog_arrow(point, size)
+    }
+    // Redrawn together with the text to keep the speaker animated.
+    if portrait {
+        draw_portrait(state, point);
+    }
+}
+
+/// Draw the current frame of the sprite (or item) that started the dialog, enlarged.
+fn draw_portrait(state: &State, point: ff::Point) {
+    const SIZE: i32 = 24;
+    let Some(speaker) = &state.dialog.speaker else {
+        return;
+    };
+    let (frames, colour_id) = match speaker {
+        Speaker::Sprite(idx) => (
+            state.sprite_frames(*idx),
+            state.game.sprites[*idx].colour_id,
+        ),
+        Speaker::Item(idx) => (state.item_frames(*idx), state.game.items[*idx].colour_id),
+    };
+    if frames.is_empty() {
+        return;
+    }
+    let image = frames.pick(state.render_frame);
+    let width = image.width();
+    let scale = SIZE / width;
+    let size = width * scale;
+    let shift = (PORTRAIT_WIDTH - size) / 2;
+    let origin = point + ff::Point::new(shift, shift);
+    let style = ff::Style::solid(COLOR_BG);
+    ff::draw_rect(origin, ff::Size::new(size, size), style);
+
+    let primary = state.colors.get(colour_id, 2);
+    let style = ff::Style::solid(ff::Color::new(primary + 1));
+    for i in 0..width * width {
+        if !image.is_set(i as usize) {
+            continue;
+        }
+        let pixel_point = ff::Point::new(i % width * scale, i / width * scale);
+        ff::draw_rect(origin + pixel_point, ff::Size::new(scale, scale), style);
     }
 }
 
-fn draw_dialog_arrow(state: &State) {
-    let y = if state.dialog.center { 89 } else { 153 };
+fn draw_dialog_arrow(point: ff::Point, size: ff::Size) {
+    let x = point.x + size.width - 11;
+    let y = point.y + 25;
     ff::draw_triangle(
-        ff::Point::new(229, y),
-        ff::Point::new(229 + 8, y),
-        ff::Point::new(229 + 4, y + 4),
+        ff::Point::new(x, y),
+        ff::Point::new(x + 8, y),
+        ff::Point::new(x + 4, y + 4),
         ff::Style::solid(COLOR_DIALOG_TEXT),
     );
 }
 
-pub fn parse_image(image: &bitsy_file::Image, primary: u8) -> Vec<u8> {
-    let pixels = &image.pixels;
+/// Draw a full-screen scrollable list, like the inventory or the dialog history.
+///
+/// Draws the centered title, the message for an empty list, and the scroll arrows.
+/// Returns the rows visible on the current page for the caller to draw.
+pub fn draw_list_overlay<'a, T>(
+    state: &State,
+    title: &str,
+    rows: &'a [T],
+    offset: usize,
+    page_size: usize,
+) -> &'a [T] {
+    ff::clear_screen(COLOR_DIALOG_BOX);
+    let font = state.font.as_font();
+    let char_width = i32::from(font.char_width());
+    let x = (ff::WIDTH - char_width * title.len() as i32) / 2;
+    ff::draw_text(title, &font, ff::Point::new(x, 10), COLOR_DIALOG_TEXT);
+
+    if rows.is_empty() {
+        const EMPTY: &str = "NOTHING YET";
+        let x = (ff::WIDTH - char_width * EMPTY.len() as i32) / 2;
+        let point = ff::Point::new(x, ff::HEIGHT / 2);
+        ff::draw_text(EMPTY, &font, point, COLOR_DIALOG_TEXT);
+        return &[];
+    }
+
+    // Show arrows if the list can be scrolled.
+    let style = ff::Style::solid(COLOR_DIALOG_TEXT);
+    if offset != 0 {
+        ff::draw_triangle(
+            ff::Point::new(229, 8),
+            ff::Point::new(229 + 8, 8),
+            ff::Point::new(229 + 4, 4),
+            style,
+        );
+    }
+    if offset + page_size < rows.len() {
+        ff::draw_triangle(
+            ff::Point::new(229, 153),
+            ff::Point::new(229 + 8, 153),
+            ff::Point::new(229 + 4, 157),
+            style,
+        );
+    }
+    let start = offset.min(rows.len());
+    let end = (offset + page_size).min(rows.len());
+    &rows[start..end]
+}
+
+/// One animation frame of a tile, a sprite, or an item.
+#[derive(Clone, Copy)]
+pub enum Frame<'a> {
+    /// Parsed from the game text, one byte per pixel.
+    Bitsy(&'a bitsy_file::Image),
+    /// Loaded from a precompiled game.
+    Packed(&'a PackedImage),
+}
+
+impl Frame<'_> {
+    /// The width (and the height) of the image in pixels.
+    pub fn width(self) -> i32 {
+        match self {
+            Self::Bitsy(image) if image.pixels.len() == 256 => 16,
+            Self::Bitsy(_) => 8,
+            Self::Packed(image) => image.width(),
+        }
+    }
+
+    /// Check if the pixel with the given index is set.
+    pub fn is_set(self, i: usize) -> bool {
+        match self {
+            Self::Bitsy(image) => image.pixels.get(i).is_some_and(|pixel| *pixel != 0),
+            Self::Packed(image) => image.is_set(i),
+        }
+    }
+}
+
+/// All animation frames of a tile, a sprite, or an item, see [`State::tile_frames`].
+#[derive(Clone, Copy)]
+pub enum Frames<'a> {
+    Bitsy(&'a [bitsy_file::Image]),
+    Packed(&'a [PackedImage]),
+}
+
+impl<'a> Frames<'a> {
+    pub fn len(self) -> usize {
+        match self {
+            Self::Bitsy(frames) => frames.len(),
+            Self::Packed(frames) => frames.len(),
+        }
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(self, i: usize) -> Option<Frame<'a>> {
+        match self {
+            Self::Bitsy(frames) => frames.get(i).map(Frame::Bitsy),
+            Self::Packed(frames) => frames.get(i).map(Frame::Packed),
+        }
+    }
+
+    /// Pick the frame to show on the given render frame.
+    ///
+    /// # Panics
+    ///
+    /// If there are no frames.
+    pub fn pick(self, frame: u16) -> Frame<'a> {
+        let frame = usize::from(frame) % self.len();
+        self.get(frame).unwrap()
+    }
+
+    pub fn iter(self) -> impl Iterator<Item = Frame<'a>> {
+        (0..self.len()).filter_map(move |i| self.get(i))
+    }
+}
+
+/// Convert a Bitsy image into a Firefly image.
+///
+/// The `primary` is the pixel value (see [`ColorMap::get`]) for the set bits.
+/// The unset bits are drawn with the background color.
+pub fn parse_image(image: Frame<'_>, primary: u8) -> Vec<u8> {
+    let mut buf = [0; MAX_IMAGE_SIZE];
+    write_image(image, primary, &mut buf).to_vec()
+}
+
+/// Convert a Bitsy image into a Firefly image stored in the given buffer.
+///
+/// Unlike [`parse_image`], doesn't allocate, so it can be used on every frame.
+pub fn write_image<'a>(
+    image: Frame<'_>,
+    primary: u8,
+    buf: &'a mut [u8; MAX_IMAGE_SIZE],
+) -> &'a [u8] {
+    let pixels = match image {
+        Frame::Bitsy(image) => &image.pixels,
+        Frame::Packed(image) => {
+            // Only recolor the set pixels. Both pixels in a byte are 0 or 1,
+            // so multiplying the byte multiplies each of them.
+            let bytes = image.as_bytes();
+            let size = bytes.len().min(MAX_IMAGE_SIZE);
+            let raw = &mut buf[..size];
+            raw.copy_from_slice(&bytes[..size]);
+            for byte in &mut raw[IMAGE_HEADER_SIZE..] {
+                *byte *= primary;
+            }
+            return raw;
+        }
+    };
     let is_hd = pixels.len() == 256;
     let width = if is_hd { 16 } else { 8 };
     let height = width;
 
-    const HEADER_SIZE: usize = 4;
     let body_size = width * height / 2;
-    let mut raw = vec![0; HEADER_SIZE + body_size as usize];
+    let raw = &mut buf[..IMAGE_HEADER_SIZE + body_size];
 
     // Header.
-    raw[0] = 0x22; // magic number
+    raw[0] = IMAGE_MAGIC; // magic number
     raw[1] = width as u8; // width
     raw[2] = (width >> 8) as u8; // width
     raw[3] = 255; // transparency
-    for i in 0..image.pixels.len() / 2 {
-        let p1 = image.pixels[i * 2] * primary;
-        let p2 = image.pixels[i * 2 + 1] * primary;
-        raw[HEADER_SIZE + i] = p1 << 4 | p2;
+    for i in 0..(pixels.len() / 2).min(body_size) {
+        let p1 = pixels[i * 2] * primary;
+        let p2 = pixels[i * 2 + 1] * primary;
+        raw[IMAGE_HEADER_SIZE + i] = p1 << 4 | p2;
     }
 
     raw
 }
 
+/// Draw a Bitsy image without allocating the converted image on the heap.
+pub fn draw_bitsy_image(image: Frame<'_>, primary: u8, point: ff::Point) {
+    let mut buf = [0; MAX_IMAGE_SIZE];
+    let raw = write_image(image, primary, &mut buf);
+    let image = unsafe { ff::Image::from_bytes(raw) };
+    ff::draw_image(&image, point);
+}
+
 fn tile_point(x: u8, y: u8) -> ff::Point {
     let x = OFFSET_X + i32::from(x) * 8;
     let y = OFFSET_Y + i32::from(y) * 8;
     ff::Point::new(x, y)
 }
 
-fn pick_frame(frames: &[bitsy_file::Image], frame: u16) -> &bitsy_file::Image {
-    let frame = usize::from(frame);
-    &frames[frame % frames.len()]
-}
-
 fn pick_raw_frame(frames: &[Image], frame: u16) -> &Image {
     let frame = usize::from(frame);
     &frames[frame % frames.len()]
@@ -361,19 +675,25 @@ fn pick_raw_frame(frames: &[Image], frame: u16) -> &Image {
 
 /// Check if the given colors have a high contrast ratio.
 fn is_contrast(c1: ff::RGB, c2: ff::RGB) -> bool {
+    // I've picked 10 as the threshold by playing with online contrast calculators
+    // and picking the minimum that still looks good. But 7 would also be ok.
+    contrast_ratio(c1, c2) >= 10.0
+}
+
+/// Get the contrast ratio of the given colors.
+///
+/// The contrast values lie on the range from 1 to 21
+/// where 1 is the same color and 21 is #FFF and #000.
+fn contrast_ratio(c1: ff::RGB, c2: ff::RGB) -> f32 {
     let l1 = luminance(c1);
     let l2 = luminance(c2);
     // https://www.accessibility-developer-guide.com/knowledge/colours-and-contrast/how-to-calculate/
-    let mut contrast = (l1 + 0.05) / (l2 + 0.05);
+    let contrast = (l1 + 0.05) / (l2 + 0.05);
     if contrast < 1.0 {
-        contrast = 1.0 / contrast;
+        1.0 / contrast
+    } else {
+        contrast
     }
-    // The contrast values lie on the range from 1 to 21
-    // where 1 is the same color and 21 is #FFF and #000.
-    //
-    // I've picked 10 as the threshold by playing with online contrast calculators
-    // and picking the minimum that still looks good. But 7 would also be ok.
-    contrast >= 10.0
 }
 
 fn luminance(c: ff::RGB) -> f32 {
@@ -397,11 +717,3 @@ fn srgb_linear(v: u8) -> f32 {
         1.28 * x * x - 0.28 * x
     }
 }
-
-pub(crate) fn randomize(x: u16) -> u32 {
-    let mut x = u32::from(x);
-    x ^= x << 13;
-    x ^= x >> 17;
-    x ^= x << 5;
-    x
-}
@@ -0,0 +1,34 @@
+/// The seed used if the given seed is zero, which xorshift cannot recover from.
+const FALLBACK_SEED: u32 = 0x9E37_79B9;
+
+/// A small pseudo-random number generator (xorshift32).
+///
+/// Seeded from the Firefly random source when the game boots
+/// or from the `seed` config option to make every run the same.
+pub struct Rng {
+    state: u32,
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        let state = if seed == 0 { FALLBACK_SEED } else { seed };
+        Self { state }
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.state = x;
+        x
+    }
+
+    /// A random number in the range from 0 (inclusive) to `n` (exclusive).
+    pub fn below(&mut self, n: usize) -> usize {
+        if n == 0 {
+            return 0;
+        }
+        ((u64::from(self.next_u32()) * n as u64) >> 32) as usize
+    }
+}
@@ -1,32 +1,119 @@
 use crate::*;
+use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::vec::Vec;
-use core::cell::OnceCell;
 use firefly_rust as ff;
 
-static mut STATE: OnceCell<State> = OnceCell::new();
-
 pub type Image = Vec<u8>;
 pub type Images = Vec<Image>;
 
+/// A screen shown on top of the game.
+///
+/// While an overlay is open, the game (including dialogs) is paused.
+#[derive(Default, Clone, Copy, PartialEq, Eq)]
+pub enum Overlay {
+    #[default]
+    None,
+    /// The list of collected items, scrolled by the given number of rows.
+    Inventory(usize),
+    /// The developer menu for warping and editing variables.
+    Dev(DevMenu),
+    /// The log of seen dialog pages, scrolled by the given number of lines.
+    History(usize),
+}
+
 pub struct State {
     pub game: bitsy_file::Game,
-    pub segments: bitsy_file::Segments,
+    /// Dialogs and endings of a precompiled game.
+    pub scripts: Scripts,
+    /// Images of a precompiled game.
+    pub frames: PackedFrames,
+    /// Handles of game objects, built when the game starts.
+    pub index: Index,
+    /// Segments to parse before the game starts.
+    ///
+    /// Freed when all of them are parsed and the game starts.
+    pub segments: Option<bitsy_file::Segments>,
     pub n_segments: usize,
+    /// Segments to parse in the background while the game is running.
+    ///
+    /// Freed when all of them are parsed.
+    pub lazy_segments: Option<bitsy_file::Segments>,
     pub room: usize,
     pub update_frame: u16,
+    /// The number of updates since the game start, stops when the game ends.
+    pub play_frames: u32,
     pub render_frame: u16,
     pub dialog_frame: u16,
     pub room_dirty: bool,
     pub held_for: u32,
+    /// If the debug info is shown on the local device, see [`draw_debug`].
+    pub show_debug: bool,
+    /// How long the button combination toggling the debug info is held.
+    pub debug_held_for: u32,
+    /// If walls are ignored, toggled in the developer menu.
+    pub noclip: bool,
     /// Input on the previous frame.
     pub dpad: ff::DPad4,
     /// Currently active dialog.
     pub dialog: Dialog,
+    /// Currently open overlay.
+    pub overlay: Overlay,
+    /// The overlay as it was last drawn, to skip redrawing overlays that don't change.
+    pub drawn_overlay: Option<Overlay>,
+    /// Dialog pages the player has already seen.
+    pub history: History,
     pub script_state: bitsy_script::State,
+    /// How many times each sequence, cycle, and shuffle block was shown.
+    ///
+    /// Saved when changed and restored when the game starts, see [`Counters::load`].
+    pub counters: Counters,
+    /// The source of randomness for the game logic, like shuffle blocks.
+    pub rng: Rng,
+    /// The source of randomness for visual effects, like shaky text.
+    ///
+    /// Separate from `rng` because rendering may differ between devices in multiplayer
+    /// and must not affect the game state.
+    pub effects_rng: Rng,
+    /// Script functions implemented in Rust that dialogs can call.
+    pub functions: Functions,
+    /// The ID of the palette currently applied to the screen and the cached tiles.
+    ///
+    /// Scripts can change the palette at any moment by modifying `script_state`,
+    /// see [`State::sync_palette`].
+    pub palette: String,
+    /// Mapping of the current palette colors to Firefly colors.
+    pub colors: ColorMap,
     /// Tiles in the current room.
     pub tiles: Vec<(u8, Images)>,
+    /// The room and the palette for which `tiles` and `colors` were built.
+    pub tiles_key: Option<(usize, String)>,
+    /// Room ID to avatar sprite ID for rooms that override the avatar (`AVA`).
+    pub room_avatars: Vec<(String, String)>,
+    /// Avatar sprite ID overridden by the current room.
+    pub room_avatar: Option<String>,
+    /// The handle of the sprite to draw as the avatar, see [`State::sync_avatar`].
+    pub avatar_sprite: Option<usize>,
     pub font: ff::FileBuf,
+    pub config: Config,
+    /// The device controlling the active player.
+    pub peer: ff::Peer,
+    /// All players in a multiplayer game. Empty in single-player.
+    pub players: Vec<Player>,
+    /// The index of the active player in `players`.
+    pub player: usize,
+    /// All peers taking turns in a hot-seat game. Empty if hot-seat is off.
+    pub turn_peers: Vec<ff::Peer>,
+    /// The index of the peer in `turn_peers` whose turn it is.
+    pub turn: usize,
+    /// Badges earned in the current session.
+    pub earned_badges: Vec<u8>,
+    /// Where the input of the active player comes from on the current update.
+    pub input: Input,
+    /// What happened in the game on the current update, see [`Engine::events`].
+    pub events: Vec<Event>,
+    /// Script function calls to apply at the end of the update, see [`Function::apply`].
+    pub calls: Vec<(ApplyFn, Vec<bitsy_script::Val>)>,
 }
 
 impl State {
@@ -42,80 +129,262 @@ impl State {
         self.script_state.pos_y = pos.y;
     }
 
-    pub fn set_room(&mut self, room_id: String) {
-        let maybe_room = self.game.rooms.iter().position(|room| room.id == room_id);
-        let Some(room_idx) = maybe_room else {
+    /// The ID of the sprite to draw as the avatar.
+    ///
+    /// The room avatar override takes precedence over the avatar set by scripts.
+    pub fn avatar_id(&self) -> &str {
+        match &self.room_avatar {
+            Some(id) => id,
+            None => &self.script_state.avatar,
+        }
+    }
+
+    /// Resolve the sprite to draw as the avatar.
+    ///
+    /// Must be called when the room, the avatar, or the index changes.
+    pub fn sync_avatar(&mut self) {
+        self.avatar_sprite = self.index.sprite(self.avatar_id());
+    }
+
+    /// Check if the game is still being parsed and cannot be started yet.
+    pub fn is_loading(&self) -> bool {
+        self.segments.is_some()
+    }
+
+    /// Check if multiple devices participate in the game.
+    pub fn is_multiplayer(&self) -> bool {
+        !self.players.is_empty() || !self.turn_peers.is_empty()
+    }
+
+    /// Close the overlay and redraw the game under it.
+    pub fn close_overlay(&mut self) {
+        self.overlay = Overlay::None;
+        self.drawn_overlay = None;
+        self.room_dirty = true;
+        self.dialog.redraw();
+    }
+
+    pub fn set_room(&mut self, room_idx: usize) {
+        let Some(room) = self.game.rooms.get(room_idx) else {
             return;
         };
+        let room_id = room.id.clone();
         self.room = room_idx;
-        self.script_state.room = room_id;
+        self.room_avatar = self
+            .room_avatars
+            .iter()
+            .find(|(id, _)| id == &room_id)
+            .map(|(_, ava)| ava.clone());
+        self.sync_avatar();
+        self.script_state.room = room_id.clone();
+        self.events.push(Event::RoomEntered(room_id));
 
         let room = &self.game.rooms[room_idx];
         if let Some(pal) = &room.palette_id {
             self.script_state.palette = pal.clone();
         }
+        self.palette = self.script_state.palette.clone();
+        self.sync_tiles();
+        self.room_dirty = true;
+    }
+
+    /// Get the currently applied palette.
+    ///
+    /// If the palette is not found, fall back to the first palette in the game.
+    pub fn get_palette(&self) -> Option<&bitsy_file::Palette> {
+        match self.index.palette(&self.palette) {
+            Some(idx) => Some(&self.game.palettes[idx]),
+            None => self.game.palettes.first(),
+        }
+    }
+
+    /// Apply the palette if it was changed by a script.
+    ///
+    /// Re-tints the cached tiles and redraws everything on the screen,
+    /// including the currently shown dialog page.
+    pub fn sync_palette(&mut self) {
+        if self.palette == self.script_state.palette {
+            return;
+        }
+        self.palette = self.script_state.palette.clone();
+        self.sync_tiles();
+        self.dialog.redraw();
+    }
+
+    /// Rebuild the cached tiles and colors if the room or the palette changed.
+    pub fn sync_tiles(&mut self) {
+        if let Some((room, palette)) = &self.tiles_key
+            && *room == self.room
+            && *palette == self.palette
+        {
+            return;
+        }
+        self.tiles_key = Some((self.room, self.palette.clone()));
+        self.reload_colors();
         self.reload_tiles();
         self.room_dirty = true;
     }
 
+    fn reload_colors(&mut self) {
+        self.colors = match self.get_palette() {
+            Some(palette) => ColorMap::new(palette),
+            None => ColorMap::default(),
+        };
+    }
+
     fn reload_tiles(&mut self) {
-        let room = &self.game.rooms[self.room];
         self.tiles.clear();
-        for (tile_id, i) in room.tiles.iter().zip(0u8..) {
-            if tile_id == "0" {
-                continue;
-            }
-            let Some(tile) = &self.game.get_tile(tile_id) else {
-                continue;
-            };
-            let tile = (*tile).clone();
-            let primary = match tile.colour_id {
-                Some(c) => c as u8,
-                None => 1,
-            };
-            let mut images = Vec::new();
-            for frame in tile.animation_frames {
-                let image = parse_image(&frame, primary);
-                images.push(image);
-            }
-            self.tiles.push((i, images));
+        for (cell, idx) in self.index.tiles_in(self.room) {
+            let primary = self.colors.get(self.game.tiles[idx].colour_id, 1);
+            let frames = self.tile_frames(idx).iter();
+            let images = frames.map(|frame| parse_image(frame, primary)).collect();
+            self.tiles.push((cell as u8, images));
         }
     }
-}
 
-fn set_state(state: State) {
-    #[allow(static_mut_refs)]
-    unsafe { STATE.set(state) }.ok().unwrap();
-}
+    /// The animation frames of the tile with the given handle.
+    pub fn tile_frames(&self, idx: usize) -> Frames<'_> {
+        match self.frames.tiles.get(idx) {
+            Some(frames) => Frames::Packed(frames),
+            None => Frames::Bitsy(&self.game.tiles[idx].animation_frames),
+        }
+    }
+
+    /// The animation frames of the sprite with the given handle.
+    pub fn sprite_frames(&self, idx: usize) -> Frames<'_> {
+        match self.frames.sprites.get(idx) {
+            Some(frames) => Frames::Packed(frames),
+            None => Frames::Bitsy(&self.game.sprites[idx].animation_frames),
+        }
+    }
 
-pub fn get_state() -> &'static mut State {
-    #[allow(static_mut_refs)]
-    unsafe { STATE.get_mut() }.unwrap()
+    /// The animation frames of the item with the given handle.
+    pub fn item_frames(&self, idx: usize) -> Frames<'_> {
+        match self.frames.items.get(idx) {
+            Some(frames) => Frames::Packed(frames),
+            None => Frames::Bitsy(&self.game.items[idx].animation_frames),
+        }
+    }
 }
 
-pub fn load_state() {
-    let raw = ff::load_file_buf("main").unwrap();
-    let raw = alloc::str::from_utf8(raw.data()).unwrap();
-    let segments = bitsy_file::Segments::new(raw);
-    let n_segments = segments.len();
-    let Some(font) = ff::load_file_buf("font") else {
-        panic!("font not found")
+/// Prepare the game for parsing from the text or the precompiled game file.
+///
+/// The game is parsed in chunks on the first updates.
+pub fn load_state(raw: Box<[u8]>, font: ff::FileBuf, config: Config) -> Result<State, LoadError> {
+    let (binary, eager, lazy) = if is_binary(&raw) {
+        let binary = load_binary(&raw)?;
+        // The game is already parsed but the segments must not be empty
+        // so that the game is initialized on the next update.
+        let eager = String::from(NOOP_SEGMENT);
+        let lazy = String::from(NOOP_SEGMENT);
+        (binary, eager, lazy)
+    } else {
+        let Ok(raw) = alloc::str::from_utf8(&raw) else {
+            return Err(LoadError::NotText);
+        };
+        // The game is parsed from the segments, only the room avatars are known in advance.
+        let binary = Binary {
+            game: bitsy_file::Game::default(),
+            room_avatars: parse_room_avatars(raw),
+            scripts: Scripts::default(),
+            frames: PackedFrames::default(),
+        };
+        let (eager, lazy) = split_game(raw);
+        (binary, eager, lazy)
     };
-    let state = State {
-        game: bitsy_file::Game::default(),
-        segments,
+    let Binary {
+        game,
+        room_avatars,
+        scripts,
+        frames,
+    } = binary;
+    // The segments keep their own copy of the game text,
+    // so free each buffer as soon as it's not needed.
+    drop(raw);
+    let segments = bitsy_file::Segments::new(&eager);
+    drop(eager);
+    let lazy_segments = bitsy_file::Segments::new(&lazy);
+    drop(lazy);
+    let n_segments = segments.len();
+    let seed = config.seed.unwrap_or_else(ff::get_random);
+    let mut rng = Rng::new(seed);
+    let effects_rng = Rng::new(rng.next_u32());
+    Ok(State {
+        game,
+        scripts,
+        frames,
+        index: Index::default(),
+        segments: Some(segments),
         n_segments,
+        lazy_segments: Some(lazy_segments),
         font,
+        config,
+        earned_badges: Vec::new(),
+        peer: ff::Peer::COMBINED,
+        players: Vec::new(),
+        player: 0,
+        turn_peers: Vec::new(),
+        turn: 0,
         room: 0,
         update_frame: 0,
+        play_frames: 0,
         render_frame: 0,
         dialog_frame: 0,
         held_for: 0,
+        show_debug: false,
+        debug_held_for: 0,
+        noclip: false,
         room_dirty: true,
         dpad: ff::DPad4::default(),
         dialog: Dialog::default(),
+        overlay: Overlay::None,
+        drawn_overlay: None,
+        history: History::default(),
         tiles: Vec::new(),
+        tiles_key: None,
+        room_avatars,
+        room_avatar: None,
+        avatar_sprite: None,
         script_state: bitsy_script::State::default(),
-    };
-    set_state(state);
+        counters: Counters::load(),
+        rng,
+        effects_rng,
+        functions: Functions::builtin(),
+        palette: String::new(),
+        colors: ColorMap::default(),
+        input: Input::Device,
+        events: Vec::new(),
+        calls: Vec::new(),
+    })
+}
+
+/// Find avatar overrides (`AVA` property) for all rooms.
+///
+/// The property is not supported by [`bitsy_file::Room`],
+/// so we extract it from the raw game file.
+pub fn parse_room_avatars(raw: &str) -> Vec<(String, String)> {
+    let mut result = Vec::new();
+    let mut room_id: Option<&str> = None;
+    for line in raw.lines() {
+        let line = line.trim_end_matches('\r');
+        if line.is_empty() {
+            room_id = None;
+            continue;
+        }
+        if let Some(id) = line.strip_prefix("ROOM ") {
+            room_id = Some(id);
+            continue;
+        }
+        if let Some(id) = line.strip_prefix("SET ") {
+            room_id = Some(id);
+            continue;
+        }
+        let Some(room_id) = room_id else {
+            continue;
+        };
+        if let Some(ava) = line.strip_prefix("AVA ") {
+            result.push((room_id.into(), ava.into()));
+        }
+    }
+    result
 }
@@ -0,0 +1,115 @@
+//! Helpers for tags spanning multiple lines, like `{choice ...}`.
+use alloc::borrow::Cow;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Replaces line breaks inside tags, see [`fold_tags`].
+pub const LINE_SEP: char = '\x1F';
+
+/// Replace line breaks inside tags to keep multi-line blocks as a single tag.
+///
+/// The tokenizer treats every line break as `{br}`, even inside a tag,
+/// which breaks blocks like `{choice ...}` spanning multiple lines.
+pub fn fold_tags(text: &str) -> Cow<'_, str> {
+    let mut depth = 0_u8;
+    let mut folded: Option<String> = None;
+    for (i, ch) in text.char_indices() {
+        match ch {
+            '{' => depth = depth.saturating_add(1),
+            '}' => depth = depth.saturating_sub(1),
+            '\n' if depth != 0 => {
+                // Copy the text only if there is something to replace.
+                let folded = folded.get_or_insert_with(|| String::from(&text[..i]));
+                folded.push(LINE_SEP);
+                continue;
+            }
+            _ => {}
+        }
+        if let Some(folded) = &mut folded {
+            folded.push(ch);
+        }
+    }
+    match folded {
+        Some(folded) => Cow::Owned(folded),
+        None => Cow::Borrowed(text),
+    }
+}
+
+/// Restore the source of a multi-line tag from its name and arguments.
+///
+/// The tag name is everything before the first space,
+/// so it may include the beginning of the first line.
+pub fn unfold_tag(name: &str, args: &str) -> String {
+    let mut text = String::with_capacity(name.len() + args.len() + 1);
+    text.push_str(name);
+    text.push(' ');
+    text.push_str(args);
+    text.replace(LINE_SEP, "\n")
+}
+
+/// Split the body of a multi-line block into options starting with `-`.
+///
+/// The lines of each option are trimmed and joined with line breaks.
+/// Lines before the first option are ignored, and so are options of nested blocks.
+pub fn split_options(body: &str) -> Vec<String> {
+    let mut options: Vec<String> = Vec::new();
+    let mut depth = 0_u8;
+    for line in body.lines() {
+        let line = line.trim_ascii();
+        if depth == 0
+            && let Some(first) = line.strip_prefix('-')
+        {
+            options.push(first.trim_ascii().into());
+        } else if let Some(option) = options.last_mut()
+            && !line.is_empty()
+        {
+            if !option.is_empty() {
+                option.push('\n');
+            }
+            option.push_str(line);
+        }
+        for ch in line.chars() {
+            match ch {
+                '{' => depth = depth.saturating_add(1),
+                '}' => depth = depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+    }
+    options
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fold_tags_keeps_text_without_tags() {
+        assert!(matches!(fold_tags("one\ntwo"), Cow::Borrowed("one\ntwo")));
+        assert!(matches!(fold_tags("{br}\n{wvy}a{wvy}"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn fold_and_unfold_round_trip() {
+        let source = "{sequence\n  - one\n  - {cycle\n    - a\n    - b\n  }\n}";
+        let folded = fold_tags(source);
+        assert!(!folded.contains('\n'));
+        // Split like the tokenizer does, without the braces.
+        let inner = &folded[1..folded.len() - 1];
+        let (name, args) = inner.split_once(' ').unwrap();
+        assert_eq!(unfold_tag(name, args), &source[1..source.len() - 1]);
+    }
+
+    #[test]
+    fn split_options_skips_nested_blocks() {
+        let body = "\n  - one\n  - {cycle\n    - a\n    - b\n  }\n  - three\n    and more\n";
+        let options = split_options(body);
+        assert_eq!(options, ["one", "{cycle\n- a\n- b\n}", "three\nand more"]);
+    }
+
+    #[test]
+    fn split_options_ignores_text_before_first_option() {
+        assert_eq!(split_options("intro\n- one\n-two"), ["one", "two"]);
+        assert!(split_options("no options").is_empty());
+    }
+}
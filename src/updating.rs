@@ -1,5 +1,7 @@
 use crate::*;
+use alloc::format;
 use alloc::string::ToString;
+use alloc::vec::Vec;
 use bitsy_file as bs;
 use firefly_rust as ff;
 
@@ -7,33 +9,91 @@ use firefly_rust as ff;
 const TILES_X: u8 = 16;
 /// The number of tiles in a column.
 const TILES_Y: u8 = 16;
+/// How many segments to parse on each update while the game is running.
+const LAZY_SEGMENTS_PER_UPDATE: usize = 4;
 
 pub fn update_state(state: &mut State) {
     state.update_frame = state.update_frame.wrapping_add(1);
-    if !state.segments.is_empty() {
+    if state.is_loading() {
         if state.update_frame != 1 {
             load_segments(state);
         }
-        if state.segments.is_empty() {
-            init_game(state)
-        }
         return;
     }
-    handle_pad(state);
+    load_lazy_segments(state, LAZY_SEGMENTS_PER_UPDATE);
+    handle_debug_toggle(state);
+    if !state.script_state.end {
+        state.play_frames = state.play_frames.saturating_add(1);
+    }
+    if state.players.is_empty() {
+        handle_pad(state);
+        state.sync_palette();
+    } else {
+        for i in 0..state.players.len() {
+            switch_player(state, i);
+            handle_pad(state);
+            state.sync_palette();
+        }
+        switch_player(state, 0);
+    }
+    award_vars(state);
     get_avatar(state).position = Some(state.pos());
+    report_memory_periodically(state);
 }
 
 fn load_segments(state: &mut State) {
+    let Some(segments) = &mut state.segments else {
+        return;
+    };
     for _ in 0..20 {
-        if let Some(segment) = state.segments.next() {
+        if let Some(segment) = segments.next() {
             state.game.push_segment(segment);
         } else {
             break;
         }
     }
+    if segments.is_empty() {
+        state.segments = None;
+        init_game(state);
+    }
+}
+
+/// Parse the given number of segments that weren't needed to start the game.
+fn load_lazy_segments(state: &mut State, n: usize) {
+    let Some(segments) = &mut state.lazy_segments else {
+        return;
+    };
+    let mut loaded = false;
+    for _ in 0..n {
+        let Some(segment) = segments.next() else {
+            break;
+        };
+        if let bs::Segment::Warning(warning) = &segment {
+            ff::log_error(warning.as_str());
+        }
+        state.game.push_segment(segment);
+        loaded = true;
+    }
+    let done = segments.is_empty();
+    if loaded {
+        state.index.update(&state.game);
+    }
+    if done {
+        state.lazy_segments = None;
+        report_memory(state);
+    }
+}
+
+/// Parse all the remaining segments.
+///
+/// Blocks the game but must be called before looking up a room or a dialog
+/// that couldn't be found because it's not parsed yet.
+pub fn finish_loading(state: &mut State) {
+    load_lazy_segments(state, usize::MAX);
 }
 
 fn init_game(state: &mut State) {
+    state.index = Index::new(&state.game);
     for warning in &state.game.warnings {
         ff::log_error(warning.as_str());
     }
@@ -42,16 +102,13 @@ fn init_game(state: &mut State) {
         state.script_state.vars.set(var.id.to_string(), val);
     }
 
-    let font = state.font.as_font();
-    let char_width = font.char_width();
-    let char_height = font.char_height();
-    state.dialog = Dialog::new(
-        &state.game.name,
-        &mut state.script_state,
-        char_width,
-        char_height,
-    );
-    set_starting_room(state)
+    let title = state.game.name.clone();
+    state.dialog = new_dialog(state, &title, true);
+    set_starting_room(state);
+    state.sync_avatar();
+    init_players(state);
+    init_turns(state);
+    report_memory(state);
 }
 
 fn set_starting_room(state: &mut State) {
@@ -66,11 +123,14 @@ fn set_starting_room(state: &mut State) {
     let Some(room_id) = &avatar.room_id else {
         return;
     };
-    state.set_room(room_id.clone());
+    if let Some(room) = state.index.room(room_id) {
+        state.set_room(room);
+    }
 }
 
 fn handle_pad(state: &mut State) {
-    let dpad = read_dpad();
+    let (dpad, buttons) = read_input(state, state.peer);
+    let dpad = buttons_to_dpad(dpad, buttons);
     if dpad.any() {
         state.held_for += 1;
     } else {
@@ -83,9 +143,31 @@ fn handle_pad(state: &mut State) {
     let pressed = dpad.just_pressed(old_dpad);
     state.dpad = dpad;
 
+    if state.overlay != Overlay::None {
+        handle_overlay_pad(state, pressed);
+        return;
+    }
+
     if state.dialog.n_pages() != 0 {
-        if pressed.any() {
-            state.dialog.next_page();
+        if state.dialog.has_choice() {
+            handle_choice_pad(state, pressed);
+        } else if pressed.any() {
+            // In multiplayer, how much of the page is rendered may differ
+            // between devices, so it cannot affect the state.
+            let page = if state.is_multiplayer() {
+                state.dialog.skip_page()
+            } else {
+                state.dialog.next_page()
+            };
+            if let Some(page) = page {
+                push_page(state, &page);
+            }
+        }
+        if state.dialog.n_pages() == 0 {
+            // Hide the dialog box that may be drawn over the room.
+            state.room_dirty = true;
+            state.events.push(Event::DialogClosed);
+            pass_turn(state, Turns::Dialog);
         }
         return;
     }
@@ -103,12 +185,57 @@ fn handle_pad(state: &mut State) {
     }
 }
 
-fn read_dpad() -> ff::DPad4 {
-    let dpad = match ff::read_pad(ff::Peer::COMBINED) {
+fn handle_overlay_pad(state: &mut State, pressed: ff::DPad4) {
+    match state.overlay {
+        Overlay::None => {}
+        Overlay::Inventory(_) => handle_inventory_pad(state, pressed),
+        Overlay::Dev(_) => handle_dev_pad(state, pressed),
+        Overlay::History(_) => handle_history_pad(state, pressed),
+    }
+}
+
+/// Handle selection of a custom item in the app menu.
+pub fn handle_menu_item(state: &mut State, index: u8) {
+    if state.is_loading() {
+        return;
+    }
+    // The menu is local to the device while overlays pause the game,
+    // which would make the state of devices in multiplayer diverge.
+    if state.is_multiplayer() {
+        return;
+    }
+    if index == MENU_INVENTORY {
+        state.overlay = Overlay::Inventory(0);
+    }
+    if index == MENU_HISTORY {
+        open_history(state);
+    }
+    if index == MENU_DEV && state.config.debug {
+        open_dev_menu(state);
+    }
+}
+
+/// Read the direction and the buttons of the peer, unless provided by the host game.
+pub fn read_input(state: &State, peer: ff::Peer) -> (ff::DPad4, ff::Buttons) {
+    if !state.is_multiplayer() {
+        match state.input {
+            Input::Device => {}
+            Input::DPad(dpad) => return (dpad, ff::Buttons::default()),
+            Input::Pad(dpad, buttons) => return (dpad, buttons),
+        }
+    }
+    let dpad = match ff::read_pad(peer) {
         Some(pad) => pad.as_dpad4(),
         None => ff::DPad4::default(),
     };
-    let buttons = ff::read_buttons(ff::Peer::COMBINED);
+    (dpad, ff::read_buttons(peer))
+}
+
+/// Let the buttons move the avatar too.
+fn buttons_to_dpad(dpad: ff::DPad4, buttons: ff::Buttons) -> ff::DPad4 {
+    if is_debug_combo(buttons) {
+        return dpad;
+    }
     if buttons.s {
         return ff::DPad4::Down;
     }
@@ -131,13 +258,16 @@ fn move_avatar_to(state: &mut State, dx: i8, dy: i8) {
     let new_pos = bs::Position { x, y };
 
     state.room_dirty = true;
-    if let Some(item) = pop_item_at(state, new_pos) {
-        let dialog_id = match &item.dialogue_id {
-            Some(id) => id,
-            None => &item.id,
-        };
-        let dialog_id = dialog_id.clone();
-        show_dialog(state, &dialog_id)
+    if let Some(idx) = pop_item_at(state, new_pos) {
+        let old_room = state.room;
+        if let Some(dialog) = resolve(state, |index| index.item_dialog(idx)) {
+            show_dialog(state, dialog, Some(Speaker::Item(idx)));
+        }
+        // The item dialog may move the avatar with `{sprite "A" ...}`,
+        // which takes precedence over the step onto the item.
+        if state.pos() != old_pos || state.room != old_room {
+            return;
+        }
     }
 
     let left_room = leave_room(state, new_pos);
@@ -149,9 +279,8 @@ fn move_avatar_to(state: &mut State, dx: i8, dy: i8) {
         return;
     }
 
-    if let Some(sprite) = get_sprite_at(state, new_pos) {
-        let sprite = sprite.clone();
-        activate_sprite(state, &sprite);
+    if let Some(idx) = get_sprite_at(state, new_pos) {
+        activate_sprite(state, idx);
         return;
     }
     if has_wall_at(state, new_pos) {
@@ -163,130 +292,211 @@ fn move_avatar_to(state: &mut State, dx: i8, dy: i8) {
 
 /// Check if the given position has the exit from the current room and activate it.
 fn leave_room(state: &mut State, new_pos: bs::Position) -> bool {
-    let room = &state.game.rooms[state.room];
-    for exit in &room.exits {
-        if exit.position != new_pos {
-            continue;
-        }
-        let pos = exit.exit.position;
-        let room_id = exit.exit.room_id.clone();
-        if let Some(dialog_id) = &exit.dialogue_id {
-            let dialog_id = dialog_id.clone();
-            show_dialog(state, &dialog_id);
-        }
-        state.set_pos(pos);
-        state.set_room(room_id);
-        return true;
+    let room = state.room;
+    let exits = &state.game.rooms[room].exits;
+    let Some(i) = exits.iter().position(|exit| exit.position == new_pos) else {
+        return false;
+    };
+    let pos = exits[i].exit.position;
+    let has_dialog = exits[i].dialogue_id.is_some();
+    let target = resolve(state, |index| index.exit_room(room, i));
+    if has_dialog && let Some(dialog) = resolve(state, |index| index.exit_dialog(room, i)) {
+        show_dialog(state, dialog, None);
     }
-    false
+    state.set_pos(pos);
+    if let Some(target) = target {
+        state.set_room(target);
+    }
+    submit_scores(state, Submit::Room);
+    pass_turn(state, Turns::Room);
+    true
 }
 
 fn activate_ending(state: &mut State, new_pos: bs::Position) -> bool {
-    let room = &state.game.rooms[state.room];
-    for ending in &room.endings {
-        if ending.position != new_pos {
-            continue;
-        }
-        let pos = ending.position;
-        let ending_id = ending.id.clone();
-        show_ending(state, &ending_id);
-        state.set_pos(pos);
-        state.script_state.end = true;
-        return true;
-    }
-    false
+    let room = state.room;
+    let endings = &state.game.rooms[room].endings;
+    let Some(i) = endings.iter().position(|ending| ending.position == new_pos) else {
+        return false;
+    };
+    let ending_id = endings[i].id.clone();
+    state.events.push(Event::EndingReached(ending_id.clone()));
+    if let Some(ending) = resolve(state, |index| index.ending_in(room, i)) {
+        show_script(state, Script::Ending(ending), None);
+    }
+    award_ending(state, &ending_id);
+    submit_scores(state, Submit::Ending);
+    state.set_pos(new_pos);
+    state.script_state.end = true;
+    true
 }
 
 /// Show dialog (if any) for the given sprite.
-fn activate_sprite(state: &mut State, sprite: &bs::Sprite) {
-    let dialog_id = match &sprite.dialogue_id {
-        Some(id) => id,
-        None => &sprite.id,
-    };
-    show_dialog(state, dialog_id)
+fn activate_sprite(state: &mut State, idx: usize) {
+    if let Some(dialog) = resolve(state, |index| index.sprite_dialog(idx)) {
+        show_dialog(state, dialog, Some(Speaker::Sprite(idx)));
+    }
 }
 
-/// Activate dialog with the given ID.
-fn show_dialog(state: &mut State, dialog_id: &str) {
-    let Some(dialog) = state.game.dialogues.iter().find(|d| d.id == dialog_id) else {
-        return;
-    };
-    show_dialog_text(state, &dialog.contents.clone());
+/// Get a handle from the index, parsing the rest of the game if it's not found.
+///
+/// The referenced object may be in a segment that is not parsed yet.
+fn resolve<F>(state: &mut State, get: F) -> Option<usize>
+where
+    F: Fn(&Index) -> Option<usize>,
+{
+    if let Some(idx) = get(&state.index) {
+        return Some(idx);
+    }
+    finish_loading(state);
+    get(&state.index)
 }
 
-fn show_ending(state: &mut State, ending_id: &str) {
-    let Some(ending) = state.game.endings.iter().find(|d| d.id == ending_id) else {
-        return;
+/// Activate the dialog with the given handle, started by the given sprite or item.
+fn show_dialog(state: &mut State, idx: usize, speaker: Option<Speaker>) {
+    let dialog_id = state.game.dialogues[idx].id.clone();
+    state.events.push(Event::DialogShown(dialog_id));
+    show_script(state, Script::Dialog(idx), speaker);
+}
+
+/// The index of a dialog or an ending in the game.
+#[derive(Clone, Copy)]
+enum Script {
+    Dialog(usize),
+    Ending(usize),
+}
+
+/// Interpret the script of a dialog or an ending and show the dialog.
+///
+/// The script is borrowed from the game rather than cloned,
+/// so that big dialogs don't need twice as much memory.
+fn show_script(state: &mut State, script: Script, speaker: Option<Speaker>) {
+    let center = matches!(script, Script::Ending(_));
+    let layout = text_layout(state, center, speaker.as_ref());
+    let old_avatar = state.script_state.avatar.clone();
+    // Dialogs and endings may have the same IDs.
+    let script_id = match script {
+        Script::Dialog(idx) => format!("DLG {}", state.game.dialogues[idx].id),
+        Script::Ending(idx) => format!("END {}", state.game.endings[idx].id),
+    };
+    let mut seen = Vec::new();
+    let blocks = Blocks::new(&mut state.counters, &script_id, &mut state.rng, &mut seen);
+    let tokens = match script {
+        Script::Dialog(idx) => state.scripts.dialogs.get(idx),
+        Script::Ending(idx) => state.scripts.endings.get(idx),
+    };
+    let script_state = &mut state.script_state;
+    state.dialog = if let Some(tokens) = tokens {
+        if tokens.is_empty() {
+            return;
+        }
+        let tokens = tokens.iter().cloned();
+        Dialog::from_tokens(tokens, script_state, layout, blocks, &state.functions)
+    } else {
+        let text = match script {
+            Script::Dialog(idx) => &state.game.dialogues[idx].contents,
+            Script::Ending(idx) => &state.game.endings[idx].dialogue,
+        };
+        if text.trim().is_empty() {
+            return;
+        }
+        Dialog::new(text, script_state, layout, blocks, &state.functions)
     };
-    show_dialog_text(state, &ending.dialogue.clone());
-    state.dialog.center = true;
+    state.dialog.center = center;
+    state.dialog.speaker = speaker;
+    state.dialog.script_id = script_id;
+    state.dialog.seen = seen;
+    apply_dialog_commands(state, &old_avatar);
 }
 
-fn show_dialog_text(state: &mut State, dialog: &str) {
-    if dialog.trim().is_empty() {
-        return;
+/// Replace the current dialog with the given script, keeping the speaker.
+///
+/// Used to show the rest of the dialog after a dialog choice.
+pub fn continue_dialog<T>(state: &mut State, tokens: T)
+where
+    T: Iterator<Item = bitsy_script::Token>,
+{
+    let center = state.dialog.center;
+    let speaker = state.dialog.speaker.take();
+    let layout = text_layout(state, center, speaker.as_ref());
+    let old_avatar = state.script_state.avatar.clone();
+    let script_id = core::mem::take(&mut state.dialog.script_id);
+    // Identical blocks before and after the choice are still told apart.
+    let mut seen = core::mem::take(&mut state.dialog.seen);
+    let blocks = Blocks::new(&mut state.counters, &script_id, &mut state.rng, &mut seen);
+    let functions = &state.functions;
+    let script_state = &mut state.script_state;
+    state.dialog = Dialog::from_tokens(tokens, script_state, layout, blocks, functions);
+    state.dialog.center = center;
+    state.dialog.speaker = speaker;
+    state.dialog.script_id = script_id;
+    state.dialog.seen = seen;
+    apply_dialog_commands(state, &old_avatar);
+}
+
+fn apply_dialog_commands(state: &mut State, old_avatar: &str) {
+    let commands = core::mem::take(&mut state.dialog.commands);
+    for command in commands {
+        apply_command(state, command);
     }
-    let font = state.font.as_font();
-    let char_width = font.char_width();
-    let char_height = font.char_height();
-    let lines = Dialog::new(dialog, &mut state.script_state, char_width, char_height);
-    state.dialog = lines;
+    // The avatar explicitly changed by the script takes precedence
+    // over the avatar override of the current room.
+    if state.script_state.avatar != old_avatar {
+        state.room_avatar = None;
+    }
+    state.sync_avatar();
+}
+
+/// Interpret the dialog script and split the text into pages.
+///
+/// Centered dialogs (the title and endings) are shown instead of the room.
+pub fn new_dialog(state: &mut State, dialog: &str, center: bool) -> Dialog {
+    const SCRIPT_ID: &str = "TITLE";
+    let layout = text_layout(state, center, None);
+    let mut seen = Vec::new();
+    let blocks = Blocks::new(&mut state.counters, SCRIPT_ID, &mut state.rng, &mut seen);
+    let functions = &state.functions;
+    let mut dialog = Dialog::new(dialog, &mut state.script_state, layout, blocks, functions);
+    dialog.center = center;
+    dialog.script_id = SCRIPT_ID.into();
+    dialog.seen = seen;
+    dialog
 }
 
+/// Get the sprite representing the player.
+///
+/// Scripts and rooms may change how the avatar looks (see [`State::avatar_id`])
+/// but the position is always tracked on the original avatar sprite.
 fn get_avatar(state: &mut State) -> &mut bs::Sprite {
-    for sprite in &mut state.game.sprites {
-        if sprite.id == state.script_state.avatar {
-            return sprite;
-        }
-    }
-    panic!("avatar not found")
+    let Some(idx) = state.index.avatar() else {
+        panic!("avatar not found")
+    };
+    &mut state.game.sprites[idx]
 }
 
 fn has_wall_at(state: &State, pos: bs::Position) -> bool {
-    let Some(tile) = get_tile_at(state, pos) else {
+    if state.noclip {
         return false;
-    };
-    if tile.wall == Some(true) {
-        return true;
-    }
-    let tile_id = tile.id.clone();
-    let room = &state.game.rooms[state.room];
-    if let Some(walls) = &room.walls {
-        for wall in walls {
-            if wall == &tile_id {
-                return true;
-            }
-        }
     }
-    false
+    let cell = pos.y * TILES_X + pos.x;
+    state.index.is_wall_at(state.room, usize::from(cell))
 }
 
-fn get_sprite_at(state: &mut State, pos: bs::Position) -> Option<&bs::Sprite> {
-    let room = &state.game.rooms[state.room];
-    for sprite in &state.game.sprites {
-        if sprite.id == "A" {
-            continue;
-        }
-        let Some(sprite_room) = sprite.room_id.as_ref() else {
-            continue;
-        };
-        if sprite_room != &room.id {
-            continue;
-        }
-        if sprite.position != Some(pos) {
-            continue;
-        }
-        return Some(sprite);
-    }
-    None
+fn get_sprite_at(state: &State, pos: bs::Position) -> Option<usize> {
+    let mut sprites = state.index.sprites_in(state.room);
+    sprites.find(|idx| state.game.sprites[*idx].position == Some(pos))
 }
 
-fn pop_item_at(state: &mut State, pos: bs::Position) -> Option<&bs::Item> {
-    let idx = get_item_idx_at(state, pos)?;
+/// Pick up the item at the given position and return its handle.
+fn pop_item_at(state: &mut State, pos: bs::Position) -> Option<usize> {
+    let i = get_item_idx_at(state, pos)?;
     let room = &mut state.game.rooms[state.room];
-    let item_ref = room.items.remove(idx);
+    let item_ref = room.items.remove(i);
+    let idx = state.index.item_in(state.room, i);
+    state.index.remove_item(state.room, i);
     state.script_state.inventory.put(item_ref.id.clone());
-    state.game.get_item(&item_ref.id)
+    award_item(state, &item_ref.id);
+    state.events.push(Event::ItemPicked(item_ref.id));
+    idx
 }
 
 fn get_item_idx_at(state: &mut State, pos: bs::Position) -> Option<usize> {
@@ -298,10 +508,3 @@ fn get_item_idx_at(state: &mut State, pos: bs::Position) -> Option<usize> {
     }
     None
 }
-
-fn get_tile_at(state: &State, pos: bs::Position) -> Option<&bs::Tile> {
-    let room = &state.game.rooms[state.room];
-    let idx = pos.y * TILES_X + pos.x;
-    let tile_id = &room.tiles[usize::from(idx)];
-    state.game.get_tile(tile_id)
-}
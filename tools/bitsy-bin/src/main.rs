@@ -0,0 +1,44 @@
+//! Convert a Bitsy game file into the precompiled binary format.
+//!
+//! The format is written and loaded by firefly-bitsy itself,
+//! see `src/precompile.rs` and `src/binary.rs`.
+//!
+//! Usage: `bitsy-bin main.bitsy.txt main.bin`
+use bitsy_file as bf;
+use firefly_bitsy::{parse_room_avatars, write_binary};
+use std::process::ExitCode;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().collect();
+    let [_, input, output] = args.as_slice() else {
+        eprintln!("usage: bitsy-bin INPUT_BITSY_FILE OUTPUT_FILE");
+        return ExitCode::FAILURE;
+    };
+    let raw = match std::fs::read_to_string(input) {
+        Ok(raw) => raw,
+        Err(err) => {
+            eprintln!("cannot read {input}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let Ok(game) = bf::Game::from(&raw) else {
+        eprintln!("{input} is not a valid Bitsy game");
+        return ExitCode::FAILURE;
+    };
+    for warning in &game.warnings {
+        eprintln!("warning: {}", warning.as_str());
+    }
+    let room_avatars = parse_room_avatars(&raw);
+    let data = match write_binary(&game, &room_avatars) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("cannot convert {input}: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if let Err(err) = std::fs::write(output, data) {
+        eprintln!("cannot write {output}: {err}");
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}